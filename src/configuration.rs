@@ -1,10 +1,12 @@
 use std::collections::{HashMap, HashSet};
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::{env, fmt, fs, io};
 
 use chrono::Utc;
 use color_eyre::Help;
+use dialoguer::console::{Color, Style};
 use dialoguer::{theme, Confirm, Input, MultiSelect, Select};
 use directories_next::{ProjectDirs, UserDirs};
 use eyre::eyre;
@@ -14,11 +16,19 @@ use serde::{Deserialize, Serialize};
 
 use crate::errors::Apologize;
 use crate::gooseberry::knowledge_base::{
-    get_handlebars, AnnotationTemplate, LinkTemplate, PageTemplate, Templates,
+    annotation_template_name, get_handlebars, AnnotationTemplate, LinkTemplate, PageTemplate,
+    Templates,
 };
 use crate::{utils, NAME};
 
 pub static DEFAULT_NESTED_TAG: &str = "/";
+pub static DEFAULT_WRAP_WIDTH: usize = 80;
+pub static DEFAULT_UPDATE_BATCH_SIZE: usize = 100;
+/// Default number of times a Hypothesis API call is retried after a network or server error
+pub static DEFAULT_MAX_RETRIES: usize = 3;
+/// Largest number of annotations the Hypothesis API returns per request
+pub static MAX_SYNC_LIMIT: u16 = 200;
+pub static DEFAULT_VIEW_HEADER_PREFIX: &str = "#";
 pub static DEFAULT_ANNOTATION_TEMPLATE: &str = r#"
 
 ### {{id}}
@@ -40,14 +50,35 @@ pub static DEFAULT_PAGE_TEMPLATE: &str = r#"
 "#;
 pub static DEFAULT_INDEX_LINK_TEMPLATE: &str = r#"
 - [{{name}}]({{relative_path}})"#;
+/// Index link template used with `link_style = "wikilink"` if the index link template isn't customized
+pub static DEFAULT_WIKILINK_INDEX_LINK_TEMPLATE: &str = r#"
+- {{wikilink name}}"#;
+pub static DEFAULT_RECENT_TEMPLATE: &str = r#"
+# Recently updated
+{{#each recent}}
+- [{{title}}]({{relative_path}}) - {{date_format "%F" updated}}{{/each}}
+
+"#;
 pub static DEFAULT_INDEX_FILENAME: &str = "SUMMARY";
+pub static DEFAULT_MEDIA_PATTERNS: &[&str] = &[
+    "youtube.com",
+    "youtu.be",
+    "vimeo.com",
+    "twitch.tv",
+    "soundcloud.com",
+    "imgur.com",
+    "flickr.com",
+    "instagram.com",
+];
 pub static DEFAULT_FILE_EXTENSION: &str = "md";
+pub static DEFAULT_MAX_FILENAME_LENGTH: usize = 250;
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum OrderBy {
     Tag,
     URI,
     BaseURI,
+    Domain,
     Title,
     ID,
     Empty,
@@ -63,6 +94,7 @@ impl fmt::Display for OrderBy {
             OrderBy::Tag => write!(f, "tag"),
             OrderBy::URI => write!(f, "uri"),
             OrderBy::BaseURI => write!(f, "base_uri"),
+            OrderBy::Domain => write!(f, "domain"),
             OrderBy::Title => write!(f, "title"),
             OrderBy::ID => write!(f, "id"),
             OrderBy::Empty => write!(f, "empty"),
@@ -74,6 +106,201 @@ impl fmt::Display for OrderBy {
     }
 }
 
+impl OrderBy {
+    /// All fields, in the order they're listed in errors
+    const ALL: [OrderBy; 11] = [
+        OrderBy::Empty,
+        OrderBy::Tag,
+        OrderBy::URI,
+        OrderBy::BaseURI,
+        OrderBy::Domain,
+        OrderBy::Title,
+        OrderBy::ID,
+        OrderBy::Created,
+        OrderBy::Updated,
+        OrderBy::Group,
+        OrderBy::GroupName,
+    ];
+
+    /// Fields to build the folder hierarchy from, `Empty` for a single file
+    pub const HIERARCHY: [OrderBy; 9] = [
+        OrderBy::Empty,
+        OrderBy::Tag,
+        OrderBy::URI,
+        OrderBy::BaseURI,
+        OrderBy::Domain,
+        OrderBy::Title,
+        OrderBy::ID,
+        OrderBy::Group,
+        OrderBy::GroupName,
+    ];
+
+    /// Fields to sort annotations within a page by
+    pub const SORT: [OrderBy; 10] = [
+        OrderBy::Tag,
+        OrderBy::URI,
+        OrderBy::BaseURI,
+        OrderBy::Domain,
+        OrderBy::ID,
+        OrderBy::Title,
+        OrderBy::Created,
+        OrderBy::Updated,
+        OrderBy::Group,
+        OrderBy::GroupName,
+    ];
+
+    /// Parses a field of the folder hierarchy, e.g. for `gooseberry config kb hierarchy tag,uri`
+    pub fn parse_hierarchy(field: &str) -> Result<Self, Apologize> {
+        Self::parse_from(field, &Self::HIERARCHY)
+    }
+
+    /// Parses a field to sort annotations within a page by, e.g. for `--sort created,tag`
+    pub fn parse_sort(field: &str) -> Result<Self, Apologize> {
+        Self::parse_from(field, &Self::SORT)
+    }
+
+    /// Parses one of `options` by its name (ignoring case), listing the options if it isn't one of them
+    fn parse_from(field: &str, options: &[OrderBy]) -> Result<Self, Apologize> {
+        options
+            .iter()
+            .copied()
+            .find(|option| option.to_string().eq_ignore_ascii_case(field.trim()))
+            .ok_or_else(|| Apologize::ConfigError {
+                message: format!(
+                    "{:?} isn't a valid field, use one of: {}",
+                    field,
+                    options
+                        .iter()
+                        .map(|option| option.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            })
+    }
+}
+
+impl FromStr for OrderBy {
+    type Err = Apologize;
+
+    fn from_str(field: &str) -> Result<Self, Self::Err> {
+        Self::parse_from(field, &Self::ALL)
+    }
+}
+
+/// What to do with replies whose parent annotation isn't synced (e.g. it's in a different group or was deleted)
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OrphanedReplies {
+    /// Keep them as top-level annotations, with `missing_parent` set for templates
+    #[default]
+    Mark,
+    /// Leave them out of the knowledge base
+    Skip,
+}
+
+/// How links between knowledge base files are written
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkStyle {
+    /// Markdown links with relative paths, e.g. for mdBook
+    #[default]
+    Markdown,
+    /// `[[wikilinks]]` by name, e.g. for Obsidian
+    Wikilink,
+}
+
+/// Section headers for the single-file knowledge base (i.e. with an empty hierarchy)
+/// and for `view --group-by`
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum SectionHeader {
+    /// One section per day on which annotations were created
+    Date,
+    /// One section per tag, annotations with multiple tags are in multiple sections
+    Tag,
+}
+
+/// Annotation fields the `--any` filter can search in
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchField {
+    Quote,
+    Tags,
+    Text,
+    Uri,
+}
+
+/// Fields searched by `--any` if `any_search_fields` isn't set
+pub static DEFAULT_ANY_SEARCH_FIELDS: &[SearchField] = &[
+    SearchField::Quote,
+    SearchField::Tags,
+    SearchField::Text,
+    SearchField::Uri,
+];
+
+/// Tags to add to annotations matching all the given conditions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagRule {
+    /// Match annotations with this pattern in their URL
+    pub(crate) if_uri_contains: Option<String>,
+    /// Match annotations with this pattern in their textual body
+    pub(crate) if_text_matches: Option<String>,
+    /// Match annotations with this pattern in the text that was annotated
+    pub(crate) if_quote_contains: Option<String>,
+    /// Tags to add to matching annotations
+    pub(crate) add_tags: Vec<String>,
+}
+
+impl TagRule {
+    /// Checks if an annotation matches all the conditions of the rule.
+    /// A rule without any conditions matches nothing.
+    pub(crate) fn matches(&self, annotation: &Annotation) -> bool {
+        if self.if_uri_contains.is_none()
+            && self.if_text_matches.is_none()
+            && self.if_quote_contains.is_none()
+        {
+            return false;
+        }
+        if let Some(pattern) = &self.if_uri_contains {
+            if !annotation.uri.contains(pattern) {
+                return false;
+            }
+        }
+        if let Some(pattern) = &self.if_text_matches {
+            if !annotation.text.contains(pattern) {
+                return false;
+            }
+        }
+        if let Some(pattern) = &self.if_quote_contains {
+            if !utils::get_quotes(annotation).join(" ").contains(pattern) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Knowledge base options for `make --profile`, unset options fall back to the top-level ones
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct KbProfile {
+    /// Directory to write out knowledge base files
+    pub(crate) kb_dir: Option<PathBuf>,
+    /// Hierarchy of folders
+    pub(crate) hierarchy: Option<Vec<OrderBy>>,
+    /// How annotations on a page are sorted
+    pub(crate) sort: Option<Vec<OrderBy>>,
+    /// Handlebars annotation template
+    pub(crate) annotation_template: Option<String>,
+    /// Handlebars page template
+    pub(crate) page_template: Option<String>,
+    /// Handlebars index link template
+    pub(crate) index_link_template: Option<String>,
+    /// Index file name
+    pub(crate) index_name: Option<String>,
+    /// Wiki file extension
+    pub(crate) file_extension: Option<String>,
+}
+
 /// Configuration struct, asks for user input to fill in the optional values the first time gooseberry is run
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GooseberryConfig {
@@ -98,10 +325,26 @@ pub struct GooseberryConfig {
     pub(crate) page_template: Option<String>,
     /// Handlebars index file name
     pub(crate) index_name: Option<String>,
+    /// Optional second, human-readable index file name (e.g. README next to an mdBook SUMMARY)
+    pub(crate) human_index_name: Option<String>,
+    /// Handlebars link template for the human-readable index file
+    pub(crate) human_index_link_template: Option<String>,
+    /// Number of most recently updated annotations to list in the index
+    pub(crate) recent_count: Option<usize>,
+    /// Handlebars template for the recently updated section of the index
+    pub(crate) recent_template: Option<String>,
+    /// Handlebars template for a header before the index links of each group of the first hierarchy level
+    pub(crate) index_section_template: Option<String>,
+    /// Handlebars template for the YAML frontmatter fields at the start of each page
+    pub(crate) page_frontmatter_template: Option<String>,
     /// Wiki file extension
     pub(crate) file_extension: Option<String>,
     /// Define the hierarchy of folders
     pub(crate) hierarchy: Option<Vec<OrderBy>>,
+    /// Maximum number of nested folders, deeper hierarchy levels are joined into the file name
+    pub(crate) max_depth: Option<usize>,
+    /// Maximum number of characters in the path of a page within the knowledge base directory
+    pub(crate) max_filename_length: Option<usize>,
     /// Define how annotations on a page are sorted
     pub(crate) sort: Option<Vec<OrderBy>>,
     /// Define tags to ignore
@@ -111,6 +354,128 @@ pub struct GooseberryConfig {
     /// Hypothesis groups with knowledge base annotations
     #[serde(default)]
     pub(crate) hypothesis_groups: HashMap<String, String>,
+    /// Rules for automatically tagging annotations with `gooseberry apply-rules`
+    #[serde(default)]
+    pub(crate) tag_rules: Vec<TagRule>,
+    /// Named knowledge base profiles, used with `make --profile <name>`
+    #[serde(default)]
+    pub(crate) profiles: HashMap<String, KbProfile>,
+    /// Leave out annotations without text or quotes when making the knowledge base and viewing
+    #[serde(default)]
+    pub(crate) skip_empty_annotations: bool,
+    /// Handlebars annotation templates keyed by tag prefix, used instead of the annotation template
+    /// for annotations with a matching tag
+    #[serde(default)]
+    pub(crate) annotation_templates: HashMap<String, String>,
+    /// Commit the knowledge base directory to git after each `make`
+    #[serde(default)]
+    pub(crate) git_autocommit: bool,
+    /// Write a mermaid graph of tags that are used together to `tags.<file_extension>` when making the knowledge base
+    #[serde(default)]
+    pub(crate) make_tag_graph: bool,
+    /// Fetch the titles of web pages for annotations without a document title when making the knowledge base
+    #[serde(default)]
+    pub(crate) fetch_titles: bool,
+    /// URI patterns of media-hosting sites, used by the `--media` filter
+    pub(crate) media_patterns: Option<Vec<String>>,
+    /// Icons (e.g. emoji) for tags, used by the `tag_icon` template helper
+    #[serde(default)]
+    pub(crate) tag_icons: HashMap<String, String>,
+    /// Folder and file names to use for URIs and base URIs in the hierarchy, instead of the URI itself
+    #[serde(default)]
+    pub(crate) uri_aliases: HashMap<String, String>,
+    /// Tags added to the local copies of annotations by these users (username or account ID),
+    /// never sent to Hypothesis
+    #[serde(default)]
+    pub(crate) user_tags: HashMap<String, String>,
+    /// How to handle replies whose parent annotation isn't synced
+    #[serde(default)]
+    pub(crate) orphaned_replies: OrphanedReplies,
+    /// Markdown links or wikilinks in the index
+    #[serde(default)]
+    pub(crate) link_style: LinkStyle,
+    /// Text between annotations in the single-file knowledge base
+    pub(crate) annotation_separator: Option<String>,
+    /// Section headers in the single-file knowledge base
+    pub(crate) section_headers: Option<SectionHeader>,
+    /// Line width used by the `wrap` template helper
+    pub(crate) wrap_width: Option<usize>,
+    /// Number of annotations sent to Hypothesis per request when tagging or moving annotations
+    pub(crate) update_batch_size: Option<usize>,
+    /// Number of annotations fetched from Hypothesis per request, between 1 and 200
+    pub(crate) sync_limit: Option<u16>,
+    /// Number of times a Hypothesis API call is retried after a network or server error
+    pub(crate) max_retries: Option<usize>,
+    /// Annotation fields searched by the `--any` filter
+    pub(crate) any_search_fields: Option<Vec<SearchField>>,
+    /// Color of section headers in `view --group-by`
+    pub(crate) view_header_color: Option<String>,
+    /// Symbol before section headers in `view --group-by`
+    pub(crate) view_header_prefix: Option<String>,
+    /// Title of feeds made with `export --format rss`
+    pub(crate) feed_title: Option<String>,
+    /// Description of feeds made with `export --format rss`
+    pub(crate) feed_description: Option<String>,
+    /// Tags added to annotations imported or moved by gooseberry
+    #[serde(default)]
+    pub(crate) default_tags: Vec<String>,
+}
+
+/// Annotation used to try out templates
+fn test_annotation() -> color_eyre::Result<Annotation> {
+    Ok(Annotation {
+        id: "test".to_string(),
+        created: Utc::now(),
+        updated: Utc::now(),
+        user: Default::default(),
+        uri: "https://github.com/out-of-cheese-error/gooseberry".to_string(),
+        text: "testing annotation".to_string(),
+        tags: vec!["tag1".to_string(), "tag2".to_string()],
+        group: "group_id".to_string(),
+        permissions: Permissions {
+            read: vec![],
+            delete: vec![],
+            admin: vec![],
+            update: vec![],
+        },
+        target: vec![Target::builder()
+            .source("https://www.example.com")
+            .selector(vec![Selector::new_quote(
+                "exact text in website to highlight",
+                "prefix of text",
+                "suffix of text",
+            )])
+            .build()?],
+        links: vec![(
+            "incontext".to_string(),
+            "https://incontext_link.com".to_string(),
+        )]
+        .into_iter()
+        .collect(),
+        hidden: false,
+        flagged: false,
+        document: Some(Document {
+            title: vec!["Web page title".into()],
+            dc: None,
+            highwire: None,
+            link: vec![],
+        }),
+        references: vec![],
+        user_info: Some(UserInfo {
+            display_name: Some("test_display_name".to_string()),
+        }),
+    })
+}
+
+/// Checks that a directory exists and files can be written in it
+fn check_writable(dir: &Path) -> Result<(), String> {
+    if !dir.is_dir() {
+        return Err("directory doesn't exist".to_string());
+    }
+    let test_file = dir.join(".gooseberry_write_test");
+    fs::write(&test_file, b"")
+        .and_then(|_| fs::remove_file(&test_file))
+        .map_err(|e| e.to_string())
 }
 
 /// Main project directory, cross-platform
@@ -133,11 +498,44 @@ impl Default for GooseberryConfig {
             page_template: None,
             index_link_template: None,
             index_name: None,
+            human_index_name: None,
+            human_index_link_template: None,
+            recent_count: None,
+            recent_template: None,
+            index_section_template: None,
+            page_frontmatter_template: None,
             file_extension: None,
             hierarchy: None,
+            max_depth: None,
+            max_filename_length: None,
             sort: None,
             ignore_tags: None,
             nested_tag: None,
+            tag_rules: Vec::new(),
+            profiles: HashMap::new(),
+            skip_empty_annotations: false,
+            annotation_templates: HashMap::new(),
+            git_autocommit: false,
+            make_tag_graph: false,
+            fetch_titles: false,
+            media_patterns: None,
+            tag_icons: HashMap::new(),
+            uri_aliases: HashMap::new(),
+            user_tags: HashMap::new(),
+            orphaned_replies: OrphanedReplies::default(),
+            link_style: LinkStyle::default(),
+            annotation_separator: None,
+            section_headers: None,
+            wrap_width: None,
+            update_batch_size: None,
+            sync_limit: None,
+            max_retries: None,
+            any_search_fields: None,
+            view_header_color: None,
+            view_header_prefix: None,
+            feed_title: None,
+            feed_description: None,
+            default_tags: Vec::new(),
         };
         config.make_dirs().expect("Couldn't make directories");
         config
@@ -184,8 +582,76 @@ file_extension = '{}'
         Ok(())
     }
 
+    /// Checks that the database and knowledge base directories don't overlap,
+    /// since clearing either one would delete the other
+    fn check_dirs(&self) -> color_eyre::Result<()> {
+        let kb_dir = match &self.kb_dir {
+            Some(kb_dir) => kb_dir,
+            None => return Ok(()),
+        };
+        let canonical = |dir: &Path| dir.canonicalize().unwrap_or_else(|_| dir.to_owned());
+        let (db_dir, kb_dir) = (canonical(&self.db_dir), canonical(kb_dir));
+        if db_dir.starts_with(&kb_dir) || kb_dir.starts_with(&db_dir) {
+            let error: color_eyre::Result<()> = Err(Apologize::ConfigError {
+                message: format!(
+                    "The database directory {:?} and the knowledge base directory {:?} overlap",
+                    db_dir, kb_dir
+                ),
+            }
+            .into());
+            return error.suggestion(
+                "Change `db_dir` or `kb_dir` in the config file (found at `gooseberry config where`) so that neither is inside the other",
+            );
+        }
+        Ok(())
+    }
+
+    /// Use the knowledge base options of a named profile for this run, without storing them
+    pub(crate) fn use_profile(&mut self, name: &str) -> color_eyre::Result<()> {
+        let profile = match self.profiles.get(name) {
+            Some(profile) => profile.clone(),
+            None => {
+                let error: color_eyre::Result<()> = Err(Apologize::ConfigError {
+                    message: format!("No knowledge base profile named {:?}", name),
+                }
+                .into());
+                return error.suggestion(format!(
+                    "Add it as `[profiles.{}]` in the config file (found at `gooseberry config where`)",
+                    name
+                ));
+            }
+        };
+        if profile.kb_dir.is_none() && self.kb_dir.is_none() {
+            let error: color_eyre::Result<()> = Err(Apologize::ConfigError {
+                message: format!("Profile {:?} has no kb_dir and none is configured", name),
+            }
+            .into());
+            return error.suggestion(format!("Set `kb_dir` under `[profiles.{}]`", name));
+        }
+        let KbProfile {
+            kb_dir,
+            hierarchy,
+            sort,
+            annotation_template,
+            page_template,
+            index_link_template,
+            index_name,
+            file_extension,
+        } = profile;
+        self.kb_dir = kb_dir.or(self.kb_dir.take());
+        self.hierarchy = hierarchy.or(self.hierarchy.take());
+        self.sort = sort.or(self.sort.take());
+        self.annotation_template = annotation_template.or(self.annotation_template.take());
+        self.page_template = page_template.or(self.page_template.take());
+        self.index_link_template = index_link_template.or(self.index_link_template.take());
+        self.index_name = index_name.or(self.index_name.take());
+        self.file_extension = file_extension.or(self.file_extension.take());
+        self.make_dirs()
+    }
+
     /// Make db and kb directories
     pub fn make_dirs(&self) -> color_eyre::Result<()> {
+        self.check_dirs()?;
         if !self.db_dir.exists() {
             fs::create_dir_all(&self.db_dir).map_err(|e: io::Error| Apologize::ConfigError {
                 message: format!(
@@ -239,14 +705,21 @@ file_extension = '{}'
     /// Get current configuration
     /// Hides the developer key (except last three digits)
     pub fn get(config_file: Option<&Path>) -> color_eyre::Result<String> {
-        let mut file = fs::File::open(Self::location(config_file)?)?;
+        let location = Self::location(config_file)?;
+        let mut file = fs::File::open(&location)?;
         let mut contents = String::new();
         file.read_to_string(&mut contents)?;
+        let config: Self = confy::load_path(&location)?;
+        // Keys read from a file are shown as is, since the path isn't secret
+        let key_in_file = config
+            .hypothesis_key
+            .as_deref()
+            .is_some_and(|key| key.starts_with("file:"));
         Ok(contents
             .split('\n')
             .map(|k| {
                 let parts = k.split(" = ").collect::<Vec<_>>();
-                if parts[0] == "hypothesis_key" {
+                if parts[0] == "hypothesis_key" && !key_in_file {
                     format!(
                         "{} = '{}{}'\n",
                         parts[0],
@@ -262,8 +735,132 @@ file_extension = '{}'
             .collect::<String>())
     }
 
-    /// Read config from default location
-    pub async fn load(config_file: Option<&Path>) -> color_eyre::Result<Self> {
+    /// Open the config file in an external editor and check that the edited config can be loaded.
+    /// Invalid edits are reported but kept in the file so they can be fixed.
+    pub async fn edit(config_file: Option<&Path>) -> color_eyre::Result<()> {
+        let location = Self::location(config_file)?;
+        if !location.exists() {
+            // Writes the default config to the default location
+            Self::load(None).await?;
+        }
+        utils::external_editor_file(&location)?;
+        match Self::load(Some(&location)).await {
+            Ok(_) => {
+                println!("Configuration at {:?} is valid", location);
+                Ok(())
+            }
+            Err(e) => Err(e).suggestion(format!(
+                "Your changes are still in {:?}, run `gooseberry config edit` again to fix them",
+                location
+            )),
+        }
+    }
+
+    /// Check that the directories, templates and Hypothesis credentials in the config work,
+    /// printing a pass/fail line for each check
+    pub async fn validate(config_file: Option<&Path>) -> color_eyre::Result<()> {
+        let config = Self::read(config_file)?;
+        let mut checks: Vec<(String, Result<(), String>)> = vec![
+            (
+                "Database and knowledge base directories don't overlap".to_string(),
+                config.check_dirs().map_err(|e| e.to_string()),
+            ),
+            (
+                format!("Database directory {:?} is writable", config.db_dir),
+                check_writable(&config.db_dir),
+            ),
+        ];
+        checks.push(match &config.kb_dir {
+            Some(kb_dir) => (
+                format!("Knowledge base directory {:?} is writable", kb_dir),
+                check_writable(kb_dir),
+            ),
+            None => (
+                "Knowledge base directory is set".to_string(),
+                Err("set it with `gooseberry config kb directory`".to_string()),
+            ),
+        });
+        checks.push((
+            "Annotation, page and index link templates render".to_string(),
+            config.check_templates().map_err(|e| e.to_string()),
+        ));
+        checks.push((
+            "Nested tag pattern isn't empty".to_string(),
+            match config.nested_tag.as_deref() {
+                Some("") => Err("set it with `gooseberry config kb nest`".to_string()),
+                _ => Ok(()),
+            },
+        ));
+        checks.push((
+            "File extension isn't empty".to_string(),
+            match config.file_extension.as_deref() {
+                Some("") => Err("set it with `gooseberry config kb extension`".to_string()),
+                _ => Ok(()),
+            },
+        ));
+        checks.push((
+            "Hypothesis credentials authorize".to_string(),
+            config.check_credentials().await,
+        ));
+
+        let num_failed = checks.iter().filter(|(_, result)| result.is_err()).count();
+        for (check, result) in &checks {
+            match result {
+                Ok(()) => println!("{} {}", Style::new().green().apply_to("✓"), check),
+                Err(e) => println!("{} {}: {}", Style::new().red().apply_to("✗"), check, e),
+            }
+        }
+        if num_failed > 0 {
+            let error: color_eyre::Result<()> = Err(Apologize::ConfigError {
+                message: format!("{} of {} checks failed", num_failed, checks.len()),
+            }
+            .into());
+            return error.suggestion(
+                "Fix the failing options with `gooseberry config edit` or `gooseberry config kb`",
+            );
+        }
+        Ok(())
+    }
+
+    /// Renders the configured annotation, page and index link templates with a test annotation
+    fn check_templates(&self) -> color_eyre::Result<()> {
+        let hbs = get_handlebars(self.get_templates())?;
+        let mut group_name_mapping = HashMap::new();
+        group_name_mapping.insert("group_id".to_owned(), "group_name".to_owned());
+        let annotation =
+            AnnotationTemplate::from_annotation(test_annotation()?, &group_name_mapping);
+        let link_data = LinkTemplate {
+            name: "page_name".to_string(),
+            relative_path: "relative/path/to/page.md".to_string(),
+            absolute_path: "absolute/path/to/page.md".to_string(),
+            depth: 2,
+            indent: "    ".to_string(),
+        };
+        let page_data = PageTemplate {
+            link_data: link_data.clone(),
+            annotations: vec![hbs.render("annotation", &annotation)?],
+            raw_annotations: vec![annotation],
+        };
+        hbs.render("page", &page_data)?;
+        hbs.render("index_link", &link_data)?;
+        Ok(())
+    }
+
+    /// Checks that the configured Hypothesis username and key authorize
+    async fn check_credentials(&self) -> Result<(), String> {
+        let key = self.get_hypothesis_key().map_err(|e| e.to_string())?;
+        match (self.hypothesis_username.as_deref(), key) {
+            (Some(name), Some(key)) => match Self::authorize(name, &key).await {
+                Ok(true) => Ok(()),
+                Ok(false) => Err("run `gooseberry config authorize` to update them".to_string()),
+                Err(e) => Err(e.to_string()),
+            },
+            _ => Err("run `gooseberry config authorize` to set them".to_string()),
+        }
+    }
+
+    /// Read config from the given or default location, without checking Hypothesis credentials
+    pub(crate) fn read(config_file: Option<&Path>) -> color_eyre::Result<Self> {
         // Reads the GOOSEBERRY_CONFIG environment variable to get config file location
         let mut config = match config_file {
             Some(path) => {
@@ -291,17 +888,31 @@ file_extension = '{}'
             },
         }?;
 
+        if let Some(sync_limit) = config.sync_limit {
+            if !(1..=MAX_SYNC_LIMIT).contains(&sync_limit) {
+                eprintln!(
+                    "sync_limit must be between 1 and {}, using {} instead of {}",
+                    MAX_SYNC_LIMIT, MAX_SYNC_LIMIT, sync_limit
+                );
+                config.sync_limit = None;
+            }
+        }
+        Ok(config)
+    }
+
+    /// Read config from default location
+    pub async fn load(config_file: Option<&Path>) -> color_eyre::Result<Self> {
+        let mut config = Self::read(config_file)?;
+
+        let key = config.get_hypothesis_key()?;
         if config.hypothesis_username.is_none()
-            || config.hypothesis_key.is_none()
+            || key.is_none()
             || !Self::authorize(
                 config
                     .hypothesis_username
                     .as_deref()
                     .ok_or_else(|| eyre!("No hypothesis username"))?,
-                config
-                    .hypothesis_key
-                    .as_deref()
-                    .ok_or_else(|| eyre!("No hypothesis key"))?,
+                key.as_deref().ok_or_else(|| eyre!("No hypothesis key"))?,
             )
             .await?
         {
@@ -319,15 +930,21 @@ file_extension = '{}'
 
     /// Queries and sets all knowledge base related configuration options
     pub fn set_kb_all(&mut self) -> color_eyre::Result<()> {
+        utils::require_interactive(
+            "Knowledge base options",
+            "Set the knowledge base directory with `gooseberry config kb directory <directory>`",
+        )?;
         self.set_kb_dir(None)?;
         self.set_annotation_template()?;
         self.set_page_template()?;
         self.set_index_link_template()?;
         self.set_index_name()?;
+        self.set_human_index()?;
+        self.set_recent()?;
         self.set_nested_tag()?;
         self.set_file_extension()?;
-        self.set_hierarchy()?;
-        self.set_sort()?;
+        self.set_hierarchy(None)?;
+        self.set_sort(None)?;
         Ok(())
     }
 
@@ -336,6 +953,7 @@ file_extension = '{}'
         if let Some(path) = directory {
             if path.exists() || fs::create_dir(path).is_ok() {
                 self.kb_dir = Some(path.to_owned());
+                self.check_dirs()?;
                 self.store()?;
                 return Ok(());
             } else {
@@ -369,6 +987,7 @@ file_extension = '{}'
                 )
             }
         };
+        self.check_dirs()?;
         self.store()?;
         Ok(())
     }
@@ -408,20 +1027,20 @@ file_extension = '{}'
         Ok(order)
     }
 
-    /// Sets the hierarchy fields which determines the folder hierarchy
-    pub fn set_hierarchy(&mut self) -> color_eyre::Result<()> {
-        println!("Set folder hierarchy order");
-        let selections = vec![
-            OrderBy::Empty,
-            OrderBy::Tag,
-            OrderBy::URI,
-            OrderBy::BaseURI,
-            OrderBy::Title,
-            OrderBy::ID,
-            OrderBy::Group,
-            OrderBy::GroupName,
-        ];
-        let order = Self::get_order_bys(selections)?;
+    /// Sets the hierarchy fields which determines the folder hierarchy.
+    /// Asks for them if not given, `Empty` makes a single file.
+    pub fn set_hierarchy(&mut self, fields: Option<&[OrderBy]>) -> color_eyre::Result<()> {
+        let order = match fields {
+            Some(fields) => fields
+                .iter()
+                .copied()
+                .filter(|&field| field != OrderBy::Empty)
+                .collect(),
+            None => {
+                println!("Set folder hierarchy order");
+                Self::get_order_bys(OrderBy::HIERARCHY.to_vec())?
+            }
+        };
         if order.is_empty() {
             println!(
                 "Single file: {}.{}",
@@ -450,21 +1069,15 @@ file_extension = '{}'
         Ok(())
     }
 
-    /// Sets the sort order for annotations within a page
-    pub fn set_sort(&mut self) -> color_eyre::Result<()> {
-        println!("Set sort order for annotations within a page");
-        let selections = vec![
-            OrderBy::Tag,
-            OrderBy::URI,
-            OrderBy::BaseURI,
-            OrderBy::ID,
-            OrderBy::Title,
-            OrderBy::Created,
-            OrderBy::Updated,
-            OrderBy::Group,
-            OrderBy::GroupName,
-        ];
-        let order = Self::get_order_bys(selections)?;
+    /// Sets the sort order for annotations within a page, asking for it if not given
+    pub fn set_sort(&mut self, fields: Option<&[OrderBy]>) -> color_eyre::Result<()> {
+        let order = match fields {
+            Some(fields) => fields.to_vec(),
+            None => {
+                println!("Set sort order for annotations within a page");
+                Self::get_order_bys(OrderBy::SORT.to_vec())?
+            }
+        };
 
         println!(
             "Sort order: {}",
@@ -507,6 +1120,14 @@ file_extension = '{}'
     }
 
     pub(crate) fn get_templates(&self) -> Templates {
+        // Wikilinks replace the default index link template, but not a customized one
+        let index_link_template = match (self.index_link_template.as_deref(), self.link_style) {
+            (Some(template), _) if template.trim() != DEFAULT_INDEX_LINK_TEMPLATE.trim() => {
+                Some(template)
+            }
+            (_, LinkStyle::Wikilink) => Some(DEFAULT_WIKILINK_INDEX_LINK_TEMPLATE),
+            (template, LinkStyle::Markdown) => template,
+        };
         Templates {
             annotation_template: self
                 .annotation_template
@@ -516,14 +1137,116 @@ file_extension = '{}'
                 .page_template
                 .as_deref()
                 .unwrap_or(DEFAULT_PAGE_TEMPLATE),
-            index_link_template: self
-                .index_link_template
+            index_link_template: index_link_template.unwrap_or(DEFAULT_INDEX_LINK_TEMPLATE),
+            human_index_link_template: self
+                .human_index_link_template
                 .as_deref()
+                .or(index_link_template)
                 .unwrap_or(DEFAULT_INDEX_LINK_TEMPLATE),
+            recent_template: self
+                .recent_template
+                .as_deref()
+                .unwrap_or(DEFAULT_RECENT_TEMPLATE),
+            index_section_template: self.index_section_template.as_deref(),
+            page_frontmatter_template: self.page_frontmatter_template.as_deref(),
+            annotation_templates: self
+                .annotation_templates
+                .iter()
+                .map(|(tag_prefix, template)| (tag_prefix.as_str(), template.as_str()))
+                .collect(),
+            tag_icons: self.tag_icons.clone(),
+            wrap_width: self.wrap_width.unwrap_or(DEFAULT_WRAP_WIDTH),
         }
     }
+
+    /// Style of section headers in `view --group-by`: bold, underlined, and in `view_header_color`
+    pub(crate) fn view_header_style(&self) -> color_eyre::Result<Style> {
+        let style = Style::new().bold().underlined();
+        let color = match self.view_header_color.as_deref() {
+            None => return Ok(style.blue()),
+            Some("black") => Color::Black,
+            Some("red") => Color::Red,
+            Some("green") => Color::Green,
+            Some("yellow") => Color::Yellow,
+            Some("blue") => Color::Blue,
+            Some("magenta") => Color::Magenta,
+            Some("cyan") => Color::Cyan,
+            Some("white") => Color::White,
+            Some(color) => {
+                let error: color_eyre::Result<Style> = Err(Apologize::ConfigError {
+                    message: format!("Unknown view_header_color {:?}", color),
+                }
+                .into());
+                return error.suggestion(
+                    "Use one of black, red, green, yellow, blue, magenta, cyan, or white",
+                );
+            }
+        };
+        Ok(style.fg(color))
+    }
+
+    /// Number of annotations fetched from Hypothesis per request
+    pub(crate) fn sync_limit(&self) -> u8 {
+        self.sync_limit
+            .filter(|sync_limit| (1..=MAX_SYNC_LIMIT).contains(sync_limit))
+            .unwrap_or(MAX_SYNC_LIMIT) as u8
+    }
+
+    /// Number of times a Hypothesis API call is retried after a network or server error
+    pub(crate) fn max_retries(&self) -> usize {
+        self.max_retries.unwrap_or(DEFAULT_MAX_RETRIES)
+    }
+
+    /// Annotation fields searched by the `--any` filter, defaulting to all of them
+    pub(crate) fn any_search_fields(&self) -> &[SearchField] {
+        self.any_search_fields
+            .as_deref()
+            .unwrap_or(DEFAULT_ANY_SEARCH_FIELDS)
+    }
+
+    /// Checks if a URI is on a media-hosting site, using `media_patterns` or the default list
+    pub(crate) fn is_media(&self, uri: &str) -> bool {
+        match &self.media_patterns {
+            Some(patterns) => patterns
+                .iter()
+                .any(|pattern| uri.contains(pattern.as_str())),
+            None => DEFAULT_MEDIA_PATTERNS
+                .iter()
+                .any(|pattern| uri.contains(pattern)),
+        }
+    }
+
+    /// Tag from `user_tags` for annotations by this account ID, if any
+    ///
+    /// An entry for the full account ID wins over one for just the username.
+    pub(crate) fn user_tag(&self, account_id: &str) -> Option<&str> {
+        self.user_tags
+            .get(account_id)
+            .or_else(|| self.user_tags.get(utils::username(account_id)))
+            .map(|tag| tag.as_str())
+            .filter(|tag| !tag.trim().is_empty())
+    }
+
+    /// Name of the handlebars template used to render an annotation with these tags.
+    ///
+    /// The longest tag prefix in `annotation_templates` matching one of the tags wins,
+    /// otherwise the default annotation template is used.
+    pub(crate) fn annotation_template_for(&self, tags: &[String]) -> String {
+        self.annotation_templates
+            .keys()
+            .filter(|tag_prefix| tags.iter().any(|tag| tag.starts_with(tag_prefix.as_str())))
+            .max_by_key(|tag_prefix| tag_prefix.len())
+            .map_or_else(
+                || "annotation".to_string(),
+                |tag_prefix| annotation_template_name(tag_prefix),
+            )
+    }
     /// Sets the annotation template in Handlebars format.
     pub fn set_annotation_template(&mut self) -> color_eyre::Result<()> {
+        utils::require_interactive(
+            "Annotation template",
+            "Set `annotation_template` in the config file (see `gooseberry config default`)",
+        )?;
         let selections = &[
             "Use default annotation template",
             "Edit annotation template",
@@ -536,48 +1259,7 @@ file_extension = '{}'
         if selection == 0 {
             self.annotation_template = Some(DEFAULT_ANNOTATION_TEMPLATE.to_string());
         } else {
-            let test_annotation = Annotation {
-                id: "test".to_string(),
-                created: Utc::now(),
-                updated: Utc::now(),
-                user: Default::default(),
-                uri: "https://github.com/out-of-cheese-error/gooseberry".to_string(),
-                text: "testing annotation".to_string(),
-                tags: vec!["tag1".to_string(), "tag2".to_string()],
-                group: "group_id".to_string(),
-                permissions: Permissions {
-                    read: vec![],
-                    delete: vec![],
-                    admin: vec![],
-                    update: vec![],
-                },
-                target: vec![Target::builder()
-                    .source("https://www.example.com")
-                    .selector(vec![Selector::new_quote(
-                        "exact text in website to highlight",
-                        "prefix of text",
-                        "suffix of text",
-                    )])
-                    .build()?],
-                links: vec![(
-                    "incontext".to_string(),
-                    "https://incontext_link.com".to_string(),
-                )]
-                .into_iter()
-                .collect(),
-                hidden: false,
-                flagged: false,
-                document: Some(Document {
-                    title: vec!["Web page title".into()],
-                    dc: None,
-                    highwire: None,
-                    link: vec![],
-                }),
-                references: vec![],
-                user_info: Some(UserInfo {
-                    display_name: Some("test_display_name".to_string()),
-                }),
-            };
+            let test_annotation = test_annotation()?;
             let mut group_name_mapping = HashMap::new();
             group_name_mapping.insert("group_id".to_owned(), "group_name".to_owned());
             let test_markdown_annotation =
@@ -693,6 +1375,8 @@ file_extension = '{}'
                     name: "page_name".to_string(),
                     relative_path: "relative/path/to/page.md".to_string(),
                     absolute_path: "absolute/path/to/page.md".to_string(),
+                    depth: 2,
+                    indent: "    ".to_string(),
                 },
                 annotations: vec![test_annotation_1.clone(), test_annotation_2.clone()]
                     .into_iter()
@@ -793,6 +1477,113 @@ file_extension = '{}'
         Ok(())
     }
 
+    /// Sets the name and link template of the optional human-readable index file.
+    /// An empty name disables it.
+    pub fn set_human_index(&mut self) -> color_eyre::Result<()> {
+        let name = utils::user_input(
+            "What name should gooseberry use for the human-readable index file (leave empty to disable)",
+            Some(self.human_index_name.as_deref().unwrap_or_default()),
+            true,
+            true,
+        )?;
+        if name.is_empty() {
+            self.human_index_name = None;
+            self.store()?;
+            return Ok(());
+        }
+        self.human_index_name = Some(name);
+        let selections = &[
+            "Use the index link template",
+            "Edit human-readable index link template",
+        ];
+        let selection = Select::with_theme(&theme::ColorfulTheme::default())
+            .with_prompt("How should gooseberry format the link in the human-readable index file?")
+            .items(&selections[..])
+            .interact()?;
+        if selection == 0 {
+            self.human_index_link_template = None;
+        } else {
+            self.human_index_link_template = loop {
+                let template = utils::external_editor_input(
+                    Some(
+                        self.human_index_link_template
+                            .as_deref()
+                            .or(self.index_link_template.as_deref())
+                            .unwrap_or(DEFAULT_INDEX_LINK_TEMPLATE),
+                    ),
+                    ".hbs",
+                )?;
+                let templates = Templates {
+                    human_index_link_template: &template,
+                    ..Default::default()
+                };
+                if let Err(e) = get_handlebars(templates) {
+                    eprintln!("TemplateRenderError: {}\n Try again.", e);
+                    continue;
+                }
+                break Some(template);
+            };
+        }
+        self.store()?;
+        Ok(())
+    }
+
+    /// Sets the number of recently updated annotations listed in the index and the template used for them.
+    /// 0 disables the section.
+    pub fn set_recent(&mut self) -> color_eyre::Result<()> {
+        let recent_count = loop {
+            let input = utils::user_input(
+                "How many recently updated annotations should gooseberry list in the index (0 to disable)",
+                Some(&self.recent_count.unwrap_or_default().to_string()),
+                true,
+                false,
+            )?;
+            match input.parse::<usize>() {
+                Ok(recent_count) => break recent_count,
+                Err(_) => println!("Please enter a number"),
+            }
+        };
+        if recent_count == 0 {
+            self.recent_count = None;
+            self.store()?;
+            return Ok(());
+        }
+        self.recent_count = Some(recent_count);
+        let selections = &[
+            "Use default recently updated template",
+            "Edit recently updated template",
+        ];
+        let selection = Select::with_theme(&theme::ColorfulTheme::default())
+            .with_prompt("How should gooseberry format the recently updated section of the index?")
+            .items(&selections[..])
+            .interact()?;
+        if selection == 0 {
+            self.recent_template = Some(DEFAULT_RECENT_TEMPLATE.to_string());
+        } else {
+            self.recent_template = loop {
+                let template = utils::external_editor_input(
+                    Some(
+                        self.recent_template
+                            .as_deref()
+                            .unwrap_or(DEFAULT_RECENT_TEMPLATE),
+                    ),
+                    ".hbs",
+                )?;
+                let templates = Templates {
+                    recent_template: &template,
+                    ..Default::default()
+                };
+                if let Err(e) = get_handlebars(templates) {
+                    eprintln!("TemplateRenderError: {}\n Try again.", e);
+                    continue;
+                }
+                break Some(template);
+            };
+        }
+        self.store()?;
+        Ok(())
+    }
+
     pub fn set_nested_tag(&mut self) -> color_eyre::Result<()> {
         self.nested_tag = Some(utils::user_input(
             "What pattern should gooseberry use to define nested tags",
@@ -822,6 +1613,10 @@ file_extension = '{}'
     /// This opens a command-line prompt where the user can select from either creating a new group or
     /// using an existing group by ID, with the option of selecting multiple groups
     pub async fn get_groups(&self, api: Hypothesis) -> color_eyre::Result<HashMap<String, String>> {
+        utils::require_interactive(
+            "Hypothesis groups",
+            "Set `hypothesis_groups = { \"<group_id>\" = \"<group name>\" }` in the config file",
+        )?;
         let selections = &[
             "Create a new Hypothesis group",
             "Use existing Hypothesis groups",
@@ -890,15 +1685,14 @@ file_extension = '{}'
     /// This opens a command-line prompt where the user can select from either creating a new group or
     /// using an existing group by ID, with the option of selecting multiple groups
     pub async fn set_groups(&mut self, group_ids: Vec<String>) -> color_eyre::Result<()> {
-        let (username, key) = (
-            self.hypothesis_username
-                .as_deref()
-                .ok_or_else(|| eyre!("No Hypothesis username"))?,
-            self.hypothesis_key
-                .as_deref()
-                .ok_or_else(|| eyre!("No Hypothesis key"))?,
-        );
-        let api = Hypothesis::new(username, key)?;
+        let key = self
+            .get_hypothesis_key()?
+            .ok_or_else(|| eyre!("No Hypothesis key"))?;
+        let username = self
+            .hypothesis_username
+            .as_deref()
+            .ok_or_else(|| eyre!("No Hypothesis username"))?;
+        let api = Hypothesis::new(username, &key)?;
         if group_ids.is_empty() {
             self.hypothesis_groups = self.get_groups(api).await?;
         } else {
@@ -928,8 +1722,55 @@ file_extension = '{}'
             == Some(UserAccountID(format!("acct:{}@hypothes.is", name))))
     }
 
+    /// Hypothesis developer API key.
+    /// If `hypothesis_key` is of the form `file:/path/to/key` the key is read from that file.
+    pub(crate) fn get_hypothesis_key(&self) -> color_eyre::Result<Option<String>> {
+        match self.hypothesis_key.as_deref() {
+            Some(key) => match key.strip_prefix("file:") {
+                Some(path) => Ok(Some(
+                    fs::read_to_string(path)
+                        .map_err(|e| Apologize::ConfigError {
+                            message: format!(
+                                "Couldn't read the Hypothesis key from {:?}: {}",
+                                path, e
+                            ),
+                        })
+                        .with_suggestion(|| {
+                            format!(
+                                "Create {:?} containing your Hypothesis developer key, \
+                                or fix the path after `file:` in `hypothesis_key`",
+                                path
+                            )
+                        })?
+                        .trim()
+                        .to_owned(),
+                )),
+                None => Ok(Some(key.to_owned())),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Sets the Hypothesis developer API key,
+    /// writing it to the key file instead if `hypothesis_key` refers to one
+    fn set_hypothesis_key(&mut self, key: String) -> color_eyre::Result<()> {
+        match self
+            .hypothesis_key
+            .as_deref()
+            .and_then(|k| k.strip_prefix("file:"))
+        {
+            Some(path) => fs::write(path, key)?,
+            None => self.hypothesis_key = Some(key),
+        }
+        Ok(())
+    }
+
     /// Asks user for Hypothesis credentials and sets them in the config
     pub async fn request_credentials(&mut self) -> color_eyre::Result<()> {
+        utils::require_interactive(
+            "Hypothesis credentials",
+            "Set valid credentials with the HYPOTHESIS_NAME and HYPOTHESIS_KEY environment variables",
+        )?;
         let mut name = String::new();
         let mut key;
         loop {
@@ -944,7 +1785,7 @@ file_extension = '{}'
                 .interact()?;
             if Self::authorize(&name, &key).await? {
                 self.hypothesis_username = Some(name);
-                self.hypothesis_key = Some(key);
+                self.set_hypothesis_key(key)?;
                 self.store()?;
                 return Ok(());
             } else {
@@ -962,7 +1803,7 @@ file_extension = '{}'
         if let (Some(n), Some(k)) = (&name, &key) {
             if Self::authorize(n, k).await? {
                 self.hypothesis_username = Some(n.to_owned());
-                self.hypothesis_key = Some(k.to_owned());
+                self.set_hypothesis_key(k.to_owned())?;
                 self.store()?;
             } else {
                 println!(
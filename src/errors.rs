@@ -8,9 +8,15 @@ pub enum Apologize {
     /// Thrown when trying to access an unrecorded tag
     #[error("You haven't tagged anything as {tag:?} yet.")]
     TagNotFound { tag: String },
+    /// Thrown when trying to rename the tag used for untagged annotations
+    #[error("{tag:?} is reserved for untagged annotations")]
+    ReservedTag { tag: String },
     /// Thrown when trying annotation ID doesn't match any recorded annotations
     #[error("Couldn't find an annotation with ID {id:?}")]
     AnnotationNotFound { id: String },
+    /// Thrown when trying to use a filter name that wasn't saved
+    #[error("Couldn't find saved filters named {name:?}")]
+    FilterNotFound { name: String },
     /// Thrown when trying to access an unrecorded group
     #[error("Couldn't access group {id:?}: {error:?}. The Group ID can be found in the URL of the group: https://hypothes.is/groups/<group_id>/<group_name>")]
     GroupNotFound { id: String, error: HypothesisError },
@@ -23,12 +29,22 @@ pub enum Apologize {
     /// Thrown when `skim` doesn't work
     #[error("SearchError: Search failed")]
     SearchError,
+
+    /// Thrown when some groups couldn't be synced
+    #[error("SyncError: Couldn't sync {num_groups} group(s)")]
+    SyncError { num_groups: usize },
     /// Errors related to changing the configuration file
     #[error("ConfigError: {message:?}")]
     ConfigError { message: String },
     /// Errors related to making the knowledge base
     #[error("KBError: {message:?}")]
     KBError { message: String },
+    /// Thrown when a `--regex` filter pattern isn't a valid regular expression
+    #[error("RegexError: {message:?}")]
+    RegexError { message: String },
+    /// Errors related to importing annotations
+    #[error("ImportError: {message:?}")]
+    ImportError { message: String },
     /// Thrown when no text is returned from an external editor
     #[error("EditorError")]
     EditorError,
@@ -1,4 +1,3 @@
-use std::io;
 use std::path::{Path, PathBuf};
 
 use chrono::{DateTime, Utc};
@@ -6,8 +5,12 @@ use clap::CommandFactory;
 use clap::Parser;
 use clap_complete::Shell;
 use hypothesis::annotations::{Order, SearchQuery, Sort};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 
-use crate::configuration::GooseberryConfig;
+use crate::configuration::{GooseberryConfig, OrderBy, SectionHeader, MAX_SYNC_LIMIT};
+use crate::errors::Apologize;
+use crate::gooseberry::export::{ExportFormat, ExportSplit};
 use crate::utils;
 use crate::NAME;
 
@@ -29,7 +32,41 @@ pub struct GooseberryCLI {
 #[derive(Parser, Debug)]
 pub enum GooseberrySubcommand {
     /// Sync newly added or updated Hypothesis annotations.
-    Sync,
+    Sync {
+        /// Only report how many annotations would be added or updated per group, without syncing
+        #[clap(long)]
+        preview: bool,
+        /// Stop at the first group that fails to sync, instead of syncing the other groups and reporting it
+        #[clap(long, conflicts_with = "preview")]
+        strict: bool,
+    },
+    /// Re-sync a single annotation by ID
+    Refresh {
+        /// ID of the annotation to fetch from Hypothesis
+        id: String,
+    },
+    /// Edit the text of an annotation in your external editor
+    Edit {
+        /// ID of the annotation to edit
+        id: String,
+    },
+    /// Create a new annotation, opening the external editor if no text is given
+    Add {
+        /// URI of the page to annotate
+        uri: String,
+        /// Text of the annotation
+        #[clap(long)]
+        text: Option<String>,
+        /// Tags to add to the annotation (comma-separated)
+        #[clap(long, value_delimiter = ',')]
+        tags: Vec<String>,
+        /// Group (ID or name) to create the annotation in, defaults to the first of gooseberry's groups by name
+        #[clap(long)]
+        group: Option<String>,
+        /// Don't add the `default_tags` from the config to the annotation
+        #[clap(long)]
+        no_default_tags: bool,
+    },
     /// Opens a search buffer to filter annotations.
     /// Has keyboard shortcuts for deleting annotations, modifying tags, and creating knowledge-base files
     Search {
@@ -49,6 +86,30 @@ pub enum GooseberrySubcommand {
         /// The tags to add to / remove from the filtered annotations (comma-separated)
         #[clap(value_delimiter = ',')]
         tag: Vec<String>,
+        /// Only print the number of matching annotations
+        #[clap(long)]
+        count: bool,
+    },
+    /// List tags with the number of annotations tagged with each
+    Tags {
+        /// Show nested tags as a tree, counting the annotations of children in their parents
+        #[clap(long)]
+        tree: bool,
+    },
+    /// Rename a tag in all annotations
+    RenameTag {
+        /// Tag to rename
+        old: String,
+        /// New name for the tag
+        new: String,
+        /// Only report how many annotations would change, without renaming
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Tag (optionally filtered) annotations using the `tag_rules` defined in the config
+    ApplyRules {
+        #[clap(flatten)]
+        filters: Filters,
     },
     /// Delete annotations in bulk
     Delete {
@@ -57,7 +118,23 @@ pub enum GooseberrySubcommand {
         /// Don't ask for confirmation
         #[clap(short, long)]
         force: bool,
+        /// Only list the ID, URI, and highlight of the annotations that would be deleted, without deleting
+        #[clap(long, conflicts_with = "force")]
+        dry_run: bool,
+        /// Tag the annotations with the first of the `ignore_tags` to hide them from the knowledge base,
+        /// instead of deleting them from Hypothesis
+        #[clap(long)]
+        soft: bool,
+        /// Only print the number of matching annotations
+        #[clap(long)]
+        count: bool,
     },
+    /// Undo the last `delete` or `tag --delete`
+    ///
+    /// Deleted annotations are created again on Hypothesis, with new IDs,
+    /// keeping their text, tags, highlighted selections, and document title
+    /// (replies become standalone annotations), and removed tags are added back
+    Undo,
     /// View (optionally filtered) annotations
     View {
         #[clap(flatten)]
@@ -65,6 +142,23 @@ pub enum GooseberrySubcommand {
         /// View annotation by ID
         #[clap(exclusive = true)]
         id: Option<String>,
+        /// Browse annotations in a terminal interface instead of printing them
+        #[clap(long)]
+        tui: bool,
+        /// Show replies right after the annotation they reply to, indented
+        #[clap(long, conflicts_with = "tui")]
+        threaded: bool,
+        /// End with a summary of the viewed annotations: count, tags, sources, and date range
+        #[clap(long, conflicts_with = "tui")]
+        stats_footer: bool,
+        /// Show annotations in sections, per day created or per tag
+        #[clap(long, value_enum, conflicts_with_all = &["tui", "threaded"])]
+        group_by: Option<SectionHeader>,
+        #[clap(flatten)]
+        limit: Limit,
+        /// Only print the number of matching annotations
+        #[clap(long, conflicts_with = "tui")]
+        count: bool,
     },
     /// Get the set of URIs from a list of (optionally filtered) annotations
     Uri {
@@ -74,6 +168,23 @@ pub enum GooseberrySubcommand {
         #[clap(value_delimiter = ',')]
         ids: Vec<String>,
     },
+    /// Print the IDs of annotations with all words of the query in their quote, tags, text, or URI.
+    /// Searches the local database without a search window, e.g. for scripts
+    Find {
+        /// Words to search for (ignoring case)
+        query: String,
+        /// Print the annotations rendered with the annotation template instead of their IDs
+        #[clap(long)]
+        markdown: bool,
+        /// Print the annotations as JSON instead of their IDs
+        #[clap(long, conflicts_with = "markdown")]
+        json: bool,
+    },
+    /// List the source URIs of (optionally filtered) annotations, with the number of annotations on each
+    Uris {
+        #[clap(flatten)]
+        filters: Filters,
+    },
     /// Create knowledge-base text files using optionally filtered annotations
     Make {
         #[clap(flatten)]
@@ -87,23 +198,120 @@ pub enum GooseberrySubcommand {
         /// Don't make an index file
         #[clap(long)]
         no_index: bool,
+        /// Render everything without writing any files, reporting all template errors and filename collisions
+        #[clap(long, conflicts_with = "clear")]
+        check: bool,
+        /// Render everything without writing any files, listing the files that are missing, out of date, or no longer generated
+        #[clap(long, conflicts_with_all = &["clear", "check"])]
+        check_drift: bool,
+        /// Build in this directory instead of the configured knowledge base directory, just for this run
+        #[clap(long, conflicts_with_all = &["check", "check_drift"])]
+        output_dir: Option<PathBuf>,
+        /// Use the knowledge base options of this profile from the config file
+        #[clap(long)]
+        profile: Option<String>,
+        /// Sort annotations within a page by these fields instead of the configured `sort` (comma-separated, e.g. created,tag)
+        #[clap(long, value_delimiter = ',', value_parser = OrderBy::parse_sort)]
+        sort: Option<Vec<OrderBy>>,
+        /// Afterwards, serve the knowledge base with `mdbook serve --open` if it has a `book.toml`,
+        /// or else open the index file
+        #[clap(long, conflicts_with_all = &["check", "check_drift"])]
+        open: bool,
+        /// Only print the number of annotations that would go into the knowledge base
+        #[clap(long)]
+        count: bool,
     },
     /// Create an index file using hierarchy and optionally filtered annotations
     Index {
         #[clap(flatten)]
         filters: Filters,
+        /// Write the index in this directory instead of the configured knowledge base directory, just for this run
+        #[clap(long)]
+        output_dir: Option<PathBuf>,
+        /// Use the knowledge base options of this profile from the config file
+        #[clap(long)]
+        profile: Option<String>,
+    },
+    /// Export (optionally filtered) annotations for use in other tools
+    Export {
+        #[clap(flatten)]
+        filters: Filters,
+        /// Format to export to
+        #[clap(long, value_enum)]
+        format: ExportFormat,
+        /// Write one file per annotated source or per day
+        #[clap(long, value_enum, default_value_t = ExportSplit::Source)]
+        split: ExportSplit,
+        /// Where to export to: a directory for Logseq (e.g. the folder of a graph, defaults to the current directory),
+        /// a file for the other formats (defaults to stdout)
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+        /// Don't ask for confirmation before overwriting existing files
+        #[clap(long)]
+        force: bool,
+        /// Anki deck to add the cards to, for `--format anki`
+        #[clap(long)]
+        deck_name: Option<String>,
+        #[clap(flatten)]
+        limit: Limit,
+        /// Sort the exported annotations by these fields (comma-separated, e.g. created,tag),
+        /// the Logseq export sorts by creation date by default
+        #[clap(long, value_delimiter = ',', value_parser = OrderBy::parse_sort)]
+        sort: Option<Vec<OrderBy>>,
+    },
+    /// Create annotations in gooseberry's group from annotation JSON, e.g. a Hypothesis export
+    Import {
+        /// JSON file with a list of annotations or a Hypothesis search response
+        #[clap(required_unless_present = "url")]
+        file: Option<PathBuf>,
+        /// Fetch the JSON from this URL instead
+        #[clap(long, conflicts_with = "file")]
+        url: Option<String>,
+        /// Group (ID or name) to create the annotations in, if gooseberry uses more than one
+        #[clap(long)]
+        to: Option<String>,
+        /// Don't add the `default_tags` from the config to the imported annotations
+        #[clap(long)]
+        no_default_tags: bool,
+        /// Only add the annotations to gooseberry's database as they are, without creating them on Hypothesis
+        #[clap(long, conflicts_with_all = &["to", "no_default_tags"])]
+        local: bool,
     },
     /// Generate shell completions
+    ///
+    /// Bash and zsh completions also complete tags and group names,
+    /// by calling gooseberry at completion time
     Complete {
         /// type of shell
         #[clap(value_enum)]
         shell: Shell,
     },
+    /// Print all tags in the database, one per line, for shell completions
+    #[clap(name = "__complete-tags", hide = true)]
+    CompleteTags,
+    /// Print the names of gooseberry's groups, one per line, for shell completions
+    #[clap(name = "__complete-groups", hide = true)]
+    CompleteGroups,
     /// Manage configuration
     Config {
         #[clap(subcommand)]
         cmd: ConfigCommand,
     },
+    /// Manage named filters
+    Filter {
+        #[clap(subcommand)]
+        cmd: FilterCommand,
+    },
+    /// Maintain the gooseberry database
+    Db {
+        #[clap(subcommand)]
+        cmd: DbCommand,
+    },
+    /// Check the Hypothesis groups you annotate in
+    Groups {
+        #[clap(subcommand)]
+        cmd: GroupsCommand,
+    },
     /// Clear all gooseberry data
     ///
     /// "ob oggle sobble obble"
@@ -114,23 +322,51 @@ pub enum GooseberrySubcommand {
     },
     /// Move (optionally filtered) annotations from a different hypothesis group to Gooseberry's
     ///
-    /// Only moves annotations created by the current user
+    /// Only moves annotations created by the current user.
+    /// Without --search, all annotations matching the filters are moved.
+    /// With --copy, the annotations are copied instead, keeping the originals
     Move {
         /// Group ID to move from
         group_id: String,
         #[clap(flatten)]
         filters: Filters,
+        /// Gooseberry group (ID or name) to move to, needed if gooseberry uses more than one group
+        #[clap(long)]
+        to: Option<String>,
         /// Open a search buffer to see and search filtered annotations to further filter them
         #[clap(short, long)]
         search: bool,
         /// Toggle fuzzy search
         #[clap(short, long, conflicts_with = "search")]
         fuzzy: bool,
+        /// Don't add the `default_tags` from the config to the moved annotations
+        #[clap(long)]
+        no_default_tags: bool,
+        /// Create copies of the annotations in gooseberry's group instead of moving them.
+        /// The copies get new IDs
+        #[clap(long)]
+        copy: bool,
     },
 }
 
-#[derive(Parser, Debug, Default, Clone)]
+/// How many annotations to keep and in which order, for `view` and `export`
+#[derive(Parser, Debug, Default, Clone, Copy)]
+pub struct Limit {
+    /// Only keep this many annotations, the oldest ones (or the newest ones with --reverse)
+    #[clap(long)]
+    pub limit: Option<usize>,
+    /// Newest annotations first
+    #[clap(long)]
+    pub reverse: bool,
+}
+
+#[derive(Parser, Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Filters {
+    /// Use filters saved with `gooseberry filter save` (other filters are ignored)
+    #[clap(long)]
+    #[serde(skip)]
+    pub saved: Option<String>,
     /// Only annotations created after this date and time
     ///
     /// Can be colloquial, e.g. "last Friday 8pm"
@@ -138,8 +374,9 @@ pub struct Filters {
     pub from: Option<DateTime<Utc>>,
     /// Only annotations created before this date and time
     ///
-    /// Can be colloquial, e.g. "last Friday 8pm"
-    #[clap(long, value_parser = utils::parse_datetime, conflicts_with = "from")]
+    /// Can be colloquial, e.g. "last Friday 8pm".
+    /// Use with --from for a date range
+    #[clap(long, value_parser = utils::parse_datetime)]
     pub before: Option<DateTime<Utc>>,
     /// Include annotations updated in given time range (instead of just created)
     #[clap(short, long)]
@@ -155,18 +392,26 @@ pub struct Filters {
     /// Only annotations with ANY of these tags (use --and to match ALL)
     #[clap(long, value_delimiter = ',')]
     pub tags: Vec<String>,
-    /// Only annotations from these groups
+    /// Only annotations from these groups, given by ID or by name
     #[clap(long, value_delimiter = ',')]
     pub groups: Vec<String>,
     /// Only annotations without ANY of these tags
     #[clap(long, value_delimiter = ',')]
     pub exclude_tags: Vec<String>,
+    /// Leave out annotations with these IDs, even if they match the other filters
+    #[clap(long, value_delimiter = ',')]
+    pub exclude_ids: Vec<String>,
     /// Only annotations that contain this text inside the text that was annotated.
     #[clap(default_value_t, long)]
     pub quote: String,
     /// Only annotations that contain this text in their textual body.
     #[clap(default_value_t, long)]
     pub text: String,
+    /// Only annotations created by one of these users (comma-separated)
+    ///
+    /// Either usernames or full account IDs, e.g. "acct:username@hypothes.is"
+    #[clap(long = "created-by", visible_alias = "author", value_delimiter = ',')]
+    pub authors: Vec<String>,
     /// Annotations NOT matching the given filter criteria
     #[clap(short, long)]
     pub not: bool,
@@ -179,21 +424,112 @@ pub struct Filters {
     /// Only annotations (i.e exclude page notes)
     #[clap(short, long, conflicts_with = "page")]
     pub annotation: bool,
+    /// Only annotations with a comment in their textual body (i.e. exclude bare highlights)
+    #[clap(long)]
+    pub has_text: bool,
+    /// Only annotations without a comment in their textual body (i.e. bare highlights)
+    #[clap(long, conflicts_with = "has_text")]
+    pub no_text: bool,
+    /// Only annotations with at least this many tags
+    #[clap(long)]
+    pub min_tags: Option<usize>,
+    /// Only annotations with at most this many tags (use 0 for untagged annotations)
+    #[clap(long)]
+    pub max_tags: Option<usize>,
+    /// Only annotations updated after they were last written to the knowledge base with `make`
+    #[clap(long)]
+    pub stale_review: bool,
+    /// Only annotations on media-hosting sites like YouTube or image hosts
+    ///
+    /// The list of URI patterns can be changed with `media_patterns` in the config file
+    #[clap(long)]
+    pub media: bool,
+    /// Match the --uri, --any, --quote, and --text patterns as regular expressions
+    #[clap(long)]
+    pub regex: bool,
+}
+
+/// A `--uri`, `--any`, `--quote`, or `--text` pattern,
+/// matched as a substring or, with `--regex`, as a regular expression
+#[derive(Debug)]
+pub enum Pattern {
+    /// No pattern given, matches everything
+    Empty,
+    Substring(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    fn new(pattern: &str, regex: bool) -> color_eyre::Result<Self> {
+        if pattern.is_empty() {
+            Ok(Pattern::Empty)
+        } else if regex {
+            Ok(Pattern::Regex(Regex::new(pattern).map_err(|e| {
+                Apologize::RegexError {
+                    message: e.to_string(),
+                }
+            })?))
+        } else {
+            Ok(Pattern::Substring(pattern.to_owned()))
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        matches!(self, Pattern::Empty)
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        match self {
+            Pattern::Empty => true,
+            Pattern::Substring(pattern) => text.contains(pattern.as_str()),
+            Pattern::Regex(regex) => regex.is_match(text),
+        }
+    }
+}
+
+/// Text patterns of a set of filters, compiled once to match against many annotations
+#[derive(Debug)]
+pub struct FilterPatterns {
+    pub uri: Pattern,
+    pub any: Pattern,
+    pub quote: Pattern,
+    pub text: Pattern,
+}
+
+impl Filters {
+    pub fn patterns(&self) -> color_eyre::Result<FilterPatterns> {
+        Ok(FilterPatterns {
+            uri: Pattern::new(&self.uri, self.regex)?,
+            any: Pattern::new(&self.any, self.regex)?,
+            quote: Pattern::new(&self.quote, self.regex)?,
+            text: Pattern::new(&self.text, self.regex)?,
+        })
+    }
 }
 
 impl From<Filters> for SearchQuery {
     fn from(filters: Filters) -> SearchQuery {
         SearchQuery {
-            limit: 200,
+            limit: MAX_SYNC_LIMIT as u8,
+            // With both --from and --before, the search starts at --from
+            // and annotations after --before are removed afterwards
             search_after: match (filters.from, filters.before) {
-                (Some(date), None) | (None, Some(date)) => date.to_rfc3339(),
+                (Some(date), _) | (None, Some(date)) => date.to_rfc3339(),
                 (None, None) => crate::MIN_DATE.to_string(),
-                _ => panic!("can't use both --from and --before"),
             },
-            uri_parts: filters.uri,
-            any: filters.any,
+            // Hypothesis only matches substrings, regex patterns are matched afterwards
+            uri_parts: if filters.regex {
+                String::new()
+            } else {
+                filters.uri
+            },
+            any: if filters.regex {
+                String::new()
+            } else {
+                filters.any
+            },
             tags: filters.tags,
-            order: if filters.before.is_some() {
+            order: if filters.from.is_none() && filters.before.is_some() {
                 Order::Desc
             } else {
                 Order::Asc
@@ -203,19 +539,103 @@ impl From<Filters> for SearchQuery {
             } else {
                 Sort::Created
             },
-            quote: filters.quote,
-            text: filters.text,
+            quote: if filters.regex {
+                String::new()
+            } else {
+                filters.quote
+            },
+            text: if filters.regex {
+                String::new()
+            } else {
+                filters.text
+            },
             group: filters.groups,
             ..SearchQuery::default()
         }
     }
 }
 
+/// Wraps the generated bash completion function to complete tags and groups
+/// with `gooseberry __complete-tags` and `gooseberry __complete-groups`
+const BASH_DYNAMIC_COMPLETIONS: &str = r#"
+_gooseberry_dynamic() {
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    local prev="${COMP_WORDS[COMP_CWORD-1]}"
+    local values
+    case "${prev}" in
+        --tags|--exclude-tags)
+            values="$(gooseberry __complete-tags 2>/dev/null)"
+            ;;
+        --to|--group|--groups)
+            values="$(gooseberry __complete-groups 2>/dev/null)"
+            ;;
+        *)
+            _gooseberry "$@"
+            return
+            ;;
+    esac
+    # Complete the last of a comma-separated list
+    local prefix=""
+    if [[ "${cur}" == *,* ]]; then
+        prefix="${cur%,*},"
+        cur="${cur##*,}"
+    fi
+    local IFS=$'\n'
+    COMPREPLY=( $(compgen -P "${prefix}" -W "${values}" -- "${cur}") )
+}
+complete -F _gooseberry_dynamic -o nosort -o bashdefault -o default gooseberry
+"#;
+
+/// Wraps the generated zsh completion function to complete tags and groups
+/// with `gooseberry __complete-tags` and `gooseberry __complete-groups`
+const ZSH_DYNAMIC_COMPLETIONS: &str = r#"
+_gooseberry_dynamic() {
+    local -a values
+    case "${words[CURRENT-1]}" in
+        --tags|--exclude-tags)
+            values=("${(@f)$(gooseberry __complete-tags 2>/dev/null)}")
+            ;;
+        --to|--group|--groups)
+            values=("${(@f)$(gooseberry __complete-groups 2>/dev/null)}")
+            ;;
+        *)
+            _gooseberry "$@"
+            return
+            ;;
+    esac
+    # Complete the last of a comma-separated list
+    compset -P '*,'
+    compadd -a values
+}
+compdef _gooseberry_dynamic gooseberry
+if [ "$funcstack[1]" = "_gooseberry" ]; then
+    _gooseberry_dynamic "$@"
+fi
+"#;
+
+/// Start of the generated zsh completion's dispatch, which calls `_gooseberry` directly
+/// when the file is autoloaded from `fpath`
+const ZSH_DISPATCH: &str = r#"if [ "$funcstack[1]" = "_gooseberry" ]; then"#;
+
 impl GooseberryCLI {
     /// Generate shell completions for gooseberry
     pub fn complete(shell: Shell) {
         let mut cmd = GooseberryCLI::command();
-        clap_complete::generate(shell, &mut cmd, NAME, &mut io::stdout());
+        let mut completions = Vec::new();
+        clap_complete::generate(shell, &mut cmd, NAME, &mut completions);
+        let mut completions = String::from_utf8_lossy(&completions).into_owned();
+        match shell {
+            Shell::Bash => completions.push_str(BASH_DYNAMIC_COMPLETIONS),
+            Shell::Zsh => {
+                // Replace the generated dispatch so that the dynamic completions are used from the first completion
+                if let Some(dispatch_start) = completions.rfind(ZSH_DISPATCH) {
+                    completions.truncate(dispatch_start);
+                }
+                completions.push_str(ZSH_DYNAMIC_COMPLETIONS);
+            }
+            _ => (),
+        }
+        print!("{}", completions);
     }
 }
 
@@ -234,6 +654,10 @@ pub enum ConfigCommand {
     Get,
     /// Prints location of currently set configuration file
     Where,
+    /// Opens the configuration file in your $EDITOR and checks that it's still valid
+    Edit,
+    /// Checks directories, templates and Hypothesis credentials, exiting with an error if any check fails
+    Validate,
     /// Change Hypothesis credentials
     Authorize,
     /// Change the groups used for Hypothesis annotations
@@ -262,18 +686,73 @@ pub enum KbConfigCommand {
     Link,
     /// Change index file name
     Index,
+    /// Change name and link template of an optional second, human-readable index file
+    HumanIndex,
+    /// Change number and template of recently updated annotations listed in the index
+    Recent,
     /// Change knowledge base file extension
     Extension,
     /// Change folder & file hierarchy
-    Hierarchy,
+    Hierarchy {
+        /// Comma-separated fields, e.g. tag,uri, or "empty" for a single file (asks if not given)
+        #[clap(value_delimiter = ',', value_parser = OrderBy::parse_hierarchy)]
+        fields: Option<Vec<OrderBy>>,
+    },
     /// Change sort order of annotations within a page
-    Sort,
+    Sort {
+        /// Comma-separated fields, e.g. created,tag (asks if not given)
+        #[clap(value_delimiter = ',', value_parser = OrderBy::parse_sort)]
+        fields: Option<Vec<OrderBy>>,
+    },
     /// Set which tags to ignore
     Ignore,
     /// Set string defining nested tags (e.g "/" => parent/child)
     Nest,
 }
 
+/// CLI options related to saved filters
+#[derive(Parser, Debug)]
+pub enum FilterCommand {
+    /// Save the given filters under a name, to use with `--saved <NAME>`
+    Save {
+        /// Name to save the filters under
+        name: String,
+        #[clap(flatten)]
+        filters: Filters,
+    },
+    /// List the names of saved filters
+    List,
+    /// Delete saved filters
+    Delete {
+        /// Name of the saved filters
+        name: String,
+    },
+}
+
+/// CLI options related to database maintenance
+#[derive(Parser, Debug)]
+pub enum DbCommand {
+    /// Flush the database to disk and report its size
+    Compact {
+        /// Also rewrite the database into a fresh one to reclaim space
+        #[clap(long)]
+        rewrite: bool,
+    },
+}
+
+/// CLI options related to Hypothesis groups
+#[derive(Parser, Debug)]
+pub enum GroupsCommand {
+    /// List groups with recent annotations of yours that aren't in the config, and so never sync
+    Missing {
+        /// Only look at annotations updated after this date (defaults to 30 days ago)
+        ///
+        /// Can be colloquial, e.g. "last Friday 8pm"
+        #[clap(long, value_parser = utils::parse_datetime)]
+        from: Option<DateTime<Utc>>,
+    },
+}
+
 impl ConfigCommand {
     /// Handle config related commands
     pub async fn run(&self, config_file: Option<&Path>) -> color_eyre::Result<()> {
@@ -288,6 +767,12 @@ impl ConfigCommand {
             Self::Where => {
                 GooseberryConfig::print_location(config_file)?;
             }
+            Self::Edit => {
+                GooseberryConfig::edit(config_file).await?;
+            }
+            Self::Validate => {
+                GooseberryConfig::validate(config_file).await?;
+            }
             Self::Authorize => {
                 let mut config = GooseberryConfig::load(config_file).await?;
                 config.request_credentials().await?;
@@ -308,10 +793,14 @@ impl ConfigCommand {
                     KbConfigCommand::Page => config.set_page_template()?,
                     KbConfigCommand::Link => config.set_index_link_template()?,
                     KbConfigCommand::Index => config.set_index_name()?,
+                    KbConfigCommand::HumanIndex => config.set_human_index()?,
+                    KbConfigCommand::Recent => config.set_recent()?,
                     KbConfigCommand::Nest => config.set_nested_tag()?,
                     KbConfigCommand::Extension => config.set_file_extension()?,
-                    KbConfigCommand::Hierarchy => config.set_hierarchy()?,
-                    KbConfigCommand::Sort => config.set_sort()?,
+                    KbConfigCommand::Hierarchy { fields } => {
+                        config.set_hierarchy(fields.as_deref())?
+                    }
+                    KbConfigCommand::Sort { fields } => config.set_sort(fields.as_deref())?,
                     KbConfigCommand::Ignore => config.set_ignore_tags()?,
                 };
             }
@@ -1,8 +1,13 @@
+use std::collections::HashSet;
+use std::fs;
 use std::path::Path;
 
+use chrono::{DateTime, Utc};
 use hypothesis::annotations::Annotation;
+use serde::{Deserialize, Serialize};
 
 use crate::errors::Apologize;
+use crate::gooseberry::cli::Filters;
 use crate::gooseberry::Gooseberry;
 use crate::utils;
 use crate::{EMPTY_TAG, MIN_DATE};
@@ -17,6 +22,21 @@ pub fn merge_index(_key: &[u8], old_indices: Option<&[u8]>, new_index: &[u8]) ->
     Some(ret)
 }
 
+/// Last destructive operation, kept so that it can be undone
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Journal {
+    /// Annotations deleted from Hypothesis, without tags added locally from `user_tags`
+    Deleted(Vec<Annotation>),
+    /// Tags removed from annotations, as (annotation ID, removed tags) pairs
+    TagsRemoved(Vec<(String, Vec<String>)>),
+}
+
+/// Key of the single last sync time, in databases from before sync times were kept per group
+const LEGACY_SYNC_TIME: &str = "last_sync_time";
+
+/// Key of the last operation in the journal tree
+const LAST_OPERATION: &[u8] = b"last";
+
 /// ## Database
 /// `sled` database related functions to create, manipulate, and retrieve information in
 /// the annotation ID: (tags IDs) tree and the tag ID: (annotation IDs) tree.
@@ -28,6 +48,43 @@ impl Gooseberry {
         Ok(sled::open(db_dir)?)
     }
 
+    /// Flushes the database to disk, optionally rewriting it into a fresh database to reclaim space.
+    /// Reports the size on disk before and after.
+    pub fn compact(&mut self, rewrite: bool) -> color_eyre::Result<()> {
+        let before = self.db.size_on_disk()?;
+        self.db.flush()?;
+        if rewrite {
+            let spinner = utils::get_spinner("Rewriting database...")?;
+            let db_dir = self.config.db_dir.clone();
+            let compact_dir = db_dir.with_extension("compact");
+            let old_dir = db_dir.with_extension("old");
+            for dir in [&compact_dir, &old_dir] {
+                if dir.exists() {
+                    fs::remove_dir_all(dir)?;
+                }
+            }
+            let compact_db = Self::get_db(&compact_dir)?;
+            compact_db.import(self.db.export());
+            compact_db.flush()?;
+            drop(compact_db);
+            // Close the current database before swapping directories
+            self.db = sled::Config::new().temporary(true).open()?;
+            fs::rename(&db_dir, &old_dir)?;
+            fs::rename(&compact_dir, &db_dir)?;
+            fs::remove_dir_all(&old_dir)?;
+            self.db = Self::get_db(&db_dir)?;
+            self.set_merge()?;
+            spinner.finish_with_message("Done!");
+        }
+        let after = self.db.size_on_disk()?;
+        println!(
+            "Database size on disk: {} -> {}",
+            indicatif::HumanBytes(before),
+            indicatif::HumanBytes(after)
+        );
+        Ok(())
+    }
+
     /// Merge function for appending items to an existing key, uses semicolons
     pub fn set_merge(&self) -> color_eyre::Result<()> {
         self.tag_to_annotations()?.set_merge_operator(merge_index);
@@ -35,21 +92,51 @@ impl Gooseberry {
         Ok(())
     }
 
-    /// (re)sets time of last sync to way in the past
+    /// Tree storing group ID: time of the last sync of that group
+    pub fn sync_times(&self) -> color_eyre::Result<sled::Tree> {
+        Ok(self.db.open_tree("sync_times")?)
+    }
+
+    /// (re)sets time of last sync of all groups to way in the past
     pub fn reset_sync_time(&self) -> color_eyre::Result<()> {
-        self.db.insert("last_sync_time", MIN_DATE.as_bytes())?;
+        self.sync_times()?.clear()?;
+        self.db.remove(LEGACY_SYNC_TIME)?;
+        Ok(())
+    }
+
+    /// Databases from before sync times were kept per group have a single last sync time.
+    /// It's moved to the groups with annotations in the database, the groups that were synced with it,
+    /// so that groups added to the config since then are back-filled
+    pub fn migrate_sync_time(&self) -> color_eyre::Result<()> {
+        let Some(date_bytes) = self.db.get(LEGACY_SYNC_TIME)? else {
+            return Ok(());
+        };
+        if self.sync_times()?.is_empty() && &*date_bytes != MIN_DATE.as_bytes() {
+            let mut groups = HashSet::new();
+            for annotation in self.iter_annotations()? {
+                groups.insert(annotation?.group);
+            }
+            let groups = groups.into_iter().collect::<Vec<_>>();
+            self.set_sync_time(&groups, std::str::from_utf8(&date_bytes)?)?;
+        }
+        self.db.remove(LEGACY_SYNC_TIME)?;
         Ok(())
     }
 
-    /// Update last sync time after sync
-    pub fn set_sync_time(&self, datetime: &str) -> color_eyre::Result<()> {
-        self.db.insert("last_sync_time", datetime.as_bytes())?;
+    /// Update last sync time of the given groups after syncing them
+    pub fn set_sync_time(&self, groups: &[String], datetime: &str) -> color_eyre::Result<()> {
+        let mut batch = sled::Batch::default();
+        for group in groups {
+            batch.insert(group.as_bytes(), datetime.as_bytes());
+        }
+        self.sync_times()?.apply_batch(batch)?;
         Ok(())
     }
 
-    /// Get time of last sync
-    pub fn get_sync_time(&self) -> color_eyre::Result<String> {
-        match self.db.get("last_sync_time")? {
+    /// Get time of last sync of a group.
+    /// Groups that were never synced start way in the past, so that they're back-filled
+    pub fn get_sync_time(&self, group: &str) -> color_eyre::Result<String> {
+        match self.sync_times()?.get(group.as_bytes())? {
             Some(date_bytes) => Ok(std::str::from_utf8(&date_bytes)?.to_owned()),
             None => Ok(MIN_DATE.to_owned()),
         }
@@ -73,14 +160,162 @@ impl Gooseberry {
         Ok(self.db.open_tree("annotations")?)
     }
 
-    /// Add an annotation to all trees
+    /// Tree storing annotation ID: time of last review (RFC 3339)
+    /// Referred to as the review times tree
+    pub fn review_times(&self) -> color_eyre::Result<sled::Tree> {
+        Ok(self.db.open_tree("review_times")?)
+    }
+
+    /// Record the time annotations were last reviewed, e.g. when they were written to the knowledge base
+    pub fn set_review_times(
+        &self,
+        ids: &[String],
+        datetime: DateTime<Utc>,
+    ) -> color_eyre::Result<()> {
+        let datetime = datetime.to_rfc3339();
+        let mut batch = sled::Batch::default();
+        for id in ids {
+            batch.insert(id.as_bytes(), datetime.as_bytes());
+        }
+        self.review_times()?.apply_batch(batch)?;
+        Ok(())
+    }
+
+    /// Time an annotation was last reviewed, if ever
+    pub fn get_review_time(&self, id: &str) -> color_eyre::Result<Option<DateTime<Utc>>> {
+        match self.review_times()?.get(id.as_bytes())? {
+            Some(date_bytes) => Ok(Some(
+                DateTime::parse_from_rfc3339(std::str::from_utf8(&date_bytes)?)?
+                    .with_timezone(&Utc),
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Tree storing name: filters
+    /// Referred to as the saved filters tree
+    pub fn saved_filters(&self) -> color_eyre::Result<sled::Tree> {
+        Ok(self.db.open_tree("saved_filters")?)
+    }
+
+    /// Save filters under a name, replacing any filters saved under the same name
+    pub fn save_filters(&self, name: &str, filters: &Filters) -> color_eyre::Result<()> {
+        let mut filters_bytes = Vec::new();
+        ciborium::ser::into_writer(filters, &mut filters_bytes)?;
+        self.saved_filters()?
+            .insert(name.as_bytes(), filters_bytes)?;
+        Ok(())
+    }
+
+    /// Retrieve filters saved under a name
+    pub fn get_saved_filters(&self, name: &str) -> color_eyre::Result<Filters> {
+        let filters_bytes =
+            self.saved_filters()?
+                .get(name.as_bytes())?
+                .ok_or(Apologize::FilterNotFound {
+                    name: name.to_owned(),
+                })?;
+        Ok(ciborium::de::from_reader(&*filters_bytes)?)
+    }
+
+    /// Names of all saved filters
+    pub fn get_saved_filter_names(&self) -> color_eyre::Result<Vec<String>> {
+        self.saved_filters()?
+            .iter()
+            .keys()
+            .map(|name| Ok(std::str::from_utf8(&name?)?.to_owned()))
+            .collect()
+    }
+
+    /// Delete filters saved under a name
+    pub fn delete_saved_filters(&self, name: &str) -> color_eyre::Result<()> {
+        self.saved_filters()?
+            .remove(name.as_bytes())?
+            .ok_or(Apologize::FilterNotFound {
+                name: name.to_owned(),
+            })?;
+        Ok(())
+    }
+
+    /// Tree storing URI: title, for web pages whose title was fetched
+    /// Referred to as the titles tree
+    pub fn titles(&self) -> color_eyre::Result<sled::Tree> {
+        Ok(self.db.open_tree("titles")?)
+    }
+
+    /// Title fetched earlier for a URI, if any
+    pub fn get_cached_title(&self, uri: &str) -> color_eyre::Result<Option<String>> {
+        match self.titles()?.get(uri.as_bytes())? {
+            Some(title_bytes) => Ok(Some(std::str::from_utf8(&title_bytes)?.to_owned())),
+            None => Ok(None),
+        }
+    }
+
+    /// Store the fetched title of a URI
+    pub fn cache_title(&self, uri: &str, title: &str) -> color_eyre::Result<()> {
+        self.titles()?.insert(uri.as_bytes(), title.as_bytes())?;
+        Ok(())
+    }
+
+    /// Tree storing annotation ID: tag, for annotations given their tag from `user_tags` locally
+    /// Referred to as the user tags tree
+    pub fn user_tagged(&self) -> color_eyre::Result<sled::Tree> {
+        Ok(self.db.open_tree("user_tagged")?)
+    }
+
+    /// Tag from `user_tags` that was added locally to an annotation, if any
+    pub fn get_local_user_tag(&self, id: &str) -> color_eyre::Result<Option<String>> {
+        match self.user_tagged()?.get(id.as_bytes())? {
+            Some(tag_bytes) => Ok(Some(std::str::from_utf8(&tag_bytes)?.to_owned())),
+            None => Ok(None),
+        }
+    }
+
+    /// Tree storing the last destructive operation
+    /// Referred to as the journal tree
+    pub fn journal(&self) -> color_eyre::Result<sled::Tree> {
+        Ok(self.db.open_tree("journal")?)
+    }
+
+    /// Record an operation in the journal, replacing the previous one
+    pub fn record_journal(&self, operation: &Journal) -> color_eyre::Result<()> {
+        let mut operation_bytes = Vec::new();
+        ciborium::ser::into_writer(operation, &mut operation_bytes)?;
+        self.journal()?.insert(LAST_OPERATION, operation_bytes)?;
+        Ok(())
+    }
+
+    /// Last operation recorded in the journal, if any
+    pub fn get_journal(&self) -> color_eyre::Result<Option<Journal>> {
+        match self.journal()?.get(LAST_OPERATION)? {
+            Some(operation_bytes) => Ok(Some(ciborium::de::from_reader(&*operation_bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Empty the journal, once its operation is undone
+    pub fn clear_journal(&self) -> color_eyre::Result<()> {
+        self.journal()?.remove(LAST_OPERATION)?;
+        Ok(())
+    }
+
+    /// Add an annotation to all trees, along with the tag from `user_tags` for its user
     pub fn add_annotation(
         &self,
-        annotation: Annotation,
+        mut annotation: Annotation,
         annotations_batch: &mut sled::Batch,
         annotation_to_tags_batch: &mut sled::Batch,
     ) -> color_eyre::Result<()> {
         let annotation_key = annotation.id.as_bytes();
+        match self.config.user_tag(&annotation.user.0) {
+            Some(tag) if !annotation.tags.iter().any(|t| t == tag) => {
+                annotation.tags.push(tag.to_owned());
+                self.user_tagged()?.insert(annotation_key, tag.as_bytes())?;
+            }
+            _ => {
+                self.user_tagged()?.remove(annotation_key)?;
+            }
+        }
         annotation_to_tags_batch.insert(annotation_key, utils::join_ids(&annotation.tags)?);
         if annotation.tags.is_empty() || !annotation.tags.iter().any(|t| !t.trim().is_empty()) {
             self.tag_to_annotations()?
@@ -100,6 +335,31 @@ impl Gooseberry {
         Ok(())
     }
 
+    /// Replace an annotation with a freshly fetched version, returning the previous version if there was one
+    pub fn refresh_annotation(
+        &self,
+        annotation: Annotation,
+    ) -> color_eyre::Result<Option<Annotation>> {
+        let previous = if self.annotations()?.contains_key(annotation.id.as_bytes())? {
+            let previous = self.get_annotation(&annotation.id)?;
+            self.delete_annotation(&annotation.id)?;
+            Some(previous)
+        } else {
+            None
+        };
+        let mut annotation_to_tags_batch = sled::Batch::default();
+        let mut annotations_batch = sled::Batch::default();
+        self.add_annotation(
+            annotation,
+            &mut annotations_batch,
+            &mut annotation_to_tags_batch,
+        )?;
+        self.annotation_to_tags()?
+            .apply_batch(annotation_to_tags_batch)?;
+        self.annotations()?.apply_batch(annotations_batch)?;
+        Ok(previous)
+    }
+
     /// add or update annotations from the Hypothesis API
     pub fn sync_annotations(
         &self,
@@ -185,6 +445,7 @@ impl Gooseberry {
             self.delete_from_tag_to_annotations_tree(tag.as_bytes(), id)?;
         }
         self.delete_from_annotations_tree(id)?;
+        self.user_tagged()?.remove(id.as_bytes())?;
         Ok(tags)
     }
 
@@ -197,6 +458,7 @@ impl Gooseberry {
             let tags = self.get_annotation_tags(id)?;
             annotation_to_tags_batch.remove(id.as_bytes());
             annotation_batch.remove(id.as_bytes());
+            self.user_tagged()?.remove(id.as_bytes())?;
             for tag in &tags {
                 self.delete_from_tag_to_annotations_tree(tag.as_bytes(), id)?;
             }
@@ -0,0 +1,496 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use color_eyre::Help;
+use hypothesis::annotations::Annotation;
+use pulldown_cmark::{Event, Parser, Tag};
+use rss::{CategoryBuilder, ChannelBuilder, GuidBuilder, ItemBuilder};
+use serde::Serialize;
+
+use crate::configuration::OrderBy;
+use crate::errors::Apologize;
+use crate::gooseberry::knowledge_base::{sort_annotations, AnnotationTemplate};
+use crate::gooseberry::Gooseberry;
+use crate::utils;
+use crate::utils::uri_to_filename;
+
+/// Formats other tools can import annotations from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// Pretty-printed JSON list of the annotations as returned by Hypothesis
+    Json,
+    /// Logseq / Roam compatible outline, one block per annotation
+    Logseq,
+    /// JSON lines with the plain text of each annotation, for search engines like Meilisearch or Tantivy
+    SearchIndex,
+    /// RSS feed with an item per annotation
+    Rss,
+    /// CSV table with a row per annotation, e.g. for spreadsheets
+    Csv,
+    /// Anki-importable TSV flashcards with the quote on the front and the text on the back
+    Anki,
+}
+
+/// Maximum number of characters of a quote used as a feed item title
+const FEED_TITLE_LENGTH: usize = 80;
+
+/// How exported annotations are split into files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportSplit {
+    /// One page per annotated source
+    Source,
+    /// One journal page per day the annotations were created
+    Day,
+}
+
+/// What `gooseberry export` writes where, besides the annotations themselves
+pub struct ExportOptions<'a> {
+    /// Format to export to
+    pub format: ExportFormat,
+    /// Whether to write one file per annotated source or per day, for Logseq
+    pub split: ExportSplit,
+    /// File or directory to export to, stdout or the current directory if not given
+    pub output: Option<&'a Path>,
+    /// Overwrite existing files without asking
+    pub force: bool,
+    /// Anki deck to add the cards to
+    pub deck_name: Option<&'a str>,
+    /// Fields to sort the exported annotations by
+    pub sort: Option<&'a [OrderBy]>,
+}
+
+/// An annotation as a search engine document
+#[derive(Debug, Serialize)]
+struct SearchIndexEntry {
+    id: String,
+    uri: String,
+    title: String,
+    tags: Vec<String>,
+    /// Plain text of the quote, text, and tags
+    content: String,
+}
+
+/// An annotation as a CSV row
+#[derive(Debug, Serialize)]
+struct CsvRow {
+    id: String,
+    created: String,
+    updated: String,
+    uri: String,
+    title: String,
+    /// Comma-separated tags
+    tags: String,
+    quote: String,
+    text: String,
+}
+
+/// Escapes text for an HTML field of an Anki TSV file, which can't contain tabs or newlines
+fn anki_field(text: &str) -> String {
+    text.trim()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\t', " ")
+        .replace('\n', "<br>")
+}
+
+/// Strips markdown formatting, keeping the text
+fn markdown_to_text(markdown: &str) -> String {
+    let mut text = String::with_capacity(markdown.len());
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Text(t) | Event::Code(t) => text.push_str(&t),
+            Event::SoftBreak => text.push(' '),
+            Event::HardBreak
+            | Event::End(Tag::Paragraph)
+            | Event::End(Tag::Heading(..))
+            | Event::End(Tag::Item)
+            | Event::End(Tag::CodeBlock(_)) => text.push('\n'),
+            _ => (),
+        }
+    }
+    text.trim().to_owned()
+}
+
+/// Renders an annotation as a Logseq block.
+///
+/// The first line is the quote (or the text for page notes), followed by the block properties.
+/// Text that comes with a quote goes into a child block.
+fn logseq_block(annotation: &AnnotationTemplate) -> String {
+    let quote = annotation.highlight.join(" ").replace('\n', " ");
+    let text = annotation.annotation.text.trim();
+    let mut lines = Vec::new();
+    let mut child = None;
+    if !quote.is_empty() {
+        lines.push(format!("- > {}", quote));
+        if !text.is_empty() {
+            child = Some(text.to_owned());
+        }
+    } else if !text.is_empty() {
+        let mut text_lines = text.lines();
+        lines.push(format!("- {}", text_lines.next().unwrap_or_default()));
+        // Continuation lines come after the properties
+        let rest = text_lines.collect::<Vec<_>>().join("\n");
+        if !rest.trim().is_empty() {
+            child = Some(rest);
+        }
+    } else {
+        lines.push(format!("- {}", annotation.title));
+    }
+    lines.push(format!(
+        "  created:: [[{}]]",
+        annotation.annotation.created.format("%F")
+    ));
+    lines.push(format!(
+        "  source:: [{}]({})",
+        annotation.title, annotation.annotation.uri
+    ));
+    lines.push(format!("  hypothesis:: {}", annotation.incontext));
+    if !annotation.annotation.tags.is_empty() {
+        lines.push(format!(
+            "  tags:: {}",
+            annotation
+                .annotation
+                .tags
+                .iter()
+                .map(|tag| format!("[[{}]]", tag))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    if let Some(child) = child {
+        let mut child_lines = child.lines();
+        lines.push(format!("  - {}", child_lines.next().unwrap_or_default()));
+        lines.extend(child_lines.map(|line| format!("    {}", line)));
+    }
+    lines.join("\n")
+}
+
+/// ## Export
+/// Writing annotations in formats meant for other tools
+impl Gooseberry {
+    /// Export annotations to `output` in the given format
+    pub fn export(
+        &self,
+        annotations: Vec<Annotation>,
+        options: ExportOptions,
+    ) -> color_eyre::Result<()> {
+        let ExportOptions {
+            format,
+            split,
+            output,
+            force,
+            deck_name,
+            sort,
+        } = options;
+        let annotations = match (sort, format) {
+            (Some(sort), _) => self.sort_export(annotations, sort),
+            (None, ExportFormat::Logseq) => self.sort_export(annotations, &[OrderBy::Created]),
+            (None, _) => annotations,
+        };
+        // Logseq exports check each page they write instead
+        if let Some(output) =
+            output.filter(|output| format != ExportFormat::Logseq && output.exists())
+        {
+            if !force
+                && !utils::confirm(
+                    &format!("Overwrite {:?}?", output),
+                    false,
+                    "Use --force to overwrite it",
+                )?
+            {
+                let error: color_eyre::Result<()> = Err(Apologize::DoingNothing.into());
+                return error.suggestion("Export to a different file or use --force");
+            }
+        }
+        match format {
+            ExportFormat::Json => Self::export_json(annotations, output),
+            ExportFormat::Logseq => self.export_logseq(
+                annotations,
+                split,
+                output.unwrap_or_else(|| Path::new(".")),
+                force,
+            ),
+            ExportFormat::SearchIndex => Self::export_search_index(annotations, output),
+            ExportFormat::Rss => self.export_rss(annotations, output),
+            ExportFormat::Csv => Self::export_csv(annotations, output),
+            ExportFormat::Anki => Self::export_anki(annotations, output, deck_name),
+        }
+    }
+
+    /// Sorts annotations like annotations within a knowledge base page
+    fn sort_export(&self, annotations: Vec<Annotation>, sort: &[OrderBy]) -> Vec<Annotation> {
+        let mut annotations: Vec<_> = annotations
+            .into_iter()
+            .map(|annotation| {
+                AnnotationTemplate::from_annotation(annotation, &self.config.hypothesis_groups)
+            })
+            .collect();
+        sort_annotations(sort, &mut annotations);
+        annotations
+            .into_iter()
+            .map(|annotation| annotation.annotation)
+            .collect()
+    }
+
+    /// Opens the export file, or stdout if there isn't one
+    fn export_writer(output: Option<&Path>) -> color_eyre::Result<io::BufWriter<Box<dyn Write>>> {
+        let writer: Box<dyn Write> = match output {
+            Some(file) => Box::new(fs::File::create(file)?),
+            None => Box::new(io::stdout()),
+        };
+        Ok(io::BufWriter::new(writer))
+    }
+
+    /// Write the annotations as a pretty-printed JSON list
+    fn export_json(annotations: Vec<Annotation>, output: Option<&Path>) -> color_eyre::Result<()> {
+        let mut writer = Self::export_writer(output)?;
+        serde_json::to_writer_pretty(&mut writer, &annotations)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Write a CSV table with a header row and a row per annotation
+    fn export_csv(annotations: Vec<Annotation>, output: Option<&Path>) -> color_eyre::Result<()> {
+        let mut writer = csv::Writer::from_writer(Self::export_writer(output)?);
+        for annotation in annotations {
+            let title = utils::document_title(&annotation)
+                .unwrap_or_default()
+                .to_owned();
+            // Fields with newlines or commas are quoted by the writer
+            writer.serialize(CsvRow {
+                created: annotation.created.to_rfc3339(),
+                updated: annotation.updated.to_rfc3339(),
+                tags: annotation.tags.join(","),
+                quote: utils::get_quotes(&annotation).join("\n"),
+                id: annotation.id,
+                uri: annotation.uri,
+                title,
+                text: annotation.text,
+            })?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Write an Anki TSV file with a card per annotation that has both a quote and text,
+    /// with the deck name in the first column if given
+    fn export_anki(
+        annotations: Vec<Annotation>,
+        output: Option<&Path>,
+        deck_name: Option<&str>,
+    ) -> color_eyre::Result<()> {
+        let mut writer = Self::export_writer(output)?;
+        // Header lines tell Anki how to read the file
+        writeln!(writer, "#separator:tab")?;
+        writeln!(writer, "#html:true")?;
+        let tags_column = if deck_name.is_some() {
+            writeln!(writer, "#deck column:1")?;
+            4
+        } else {
+            3
+        };
+        writeln!(writer, "#tags column:{}", tags_column)?;
+        let mut num_cards = 0;
+        for annotation in annotations {
+            let quote = utils::get_quotes(&annotation).join("\n");
+            if quote.trim().is_empty() || annotation.text.trim().is_empty() {
+                continue;
+            }
+            // Anki tags are separated by spaces
+            let tags = annotation
+                .tags
+                .iter()
+                .map(|tag| tag.replace(' ', "_"))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let mut fields = vec![anki_field(&quote), anki_field(&annotation.text), tags];
+            if let Some(deck_name) = deck_name {
+                // Deck names are plain text, not HTML
+                fields.insert(0, deck_name.to_owned());
+            }
+            writeln!(writer, "{}", fields.join("\t"))?;
+            num_cards += 1;
+        }
+        writer.flush()?;
+        if output.is_some() {
+            println!("Exported {} card(s)", num_cards);
+        }
+        Ok(())
+    }
+
+    /// Write one JSON line per annotation with markdown stripped from its quote and text
+    fn export_search_index(
+        annotations: Vec<Annotation>,
+        output: Option<&Path>,
+    ) -> color_eyre::Result<()> {
+        let mut writer = Self::export_writer(output)?;
+        for annotation in annotations {
+            let mut content = utils::get_quotes(&annotation)
+                .into_iter()
+                .map(markdown_to_text)
+                .collect::<Vec<_>>();
+            content.push(markdown_to_text(&annotation.text));
+            content.push(annotation.tags.join(" "));
+            content.retain(|c| !c.is_empty());
+            let title = utils::document_title(&annotation)
+                .unwrap_or_default()
+                .to_owned();
+            let entry = SearchIndexEntry {
+                content: content.join("\n"),
+                id: annotation.id,
+                uri: annotation.uri,
+                title,
+                tags: annotation.tags,
+            };
+            serde_json::to_writer(&mut writer, &entry)?;
+            writeln!(writer)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Write an RSS feed with the newest annotations first
+    fn export_rss(
+        &self,
+        mut annotations: Vec<Annotation>,
+        output: Option<&Path>,
+    ) -> color_eyre::Result<()> {
+        annotations.sort_by(|a, b| b.created.cmp(&a.created));
+        let items: Vec<_> = annotations
+            .into_iter()
+            .map(|annotation| {
+                let annotation =
+                    AnnotationTemplate::from_annotation(annotation, &self.config.hypothesis_groups);
+                let quote = annotation.highlight.join(" ").replace('\n', " ");
+                let title = if quote.is_empty() {
+                    annotation.title.to_owned()
+                } else if quote.chars().count() > FEED_TITLE_LENGTH {
+                    format!(
+                        "{}…",
+                        quote.chars().take(FEED_TITLE_LENGTH).collect::<String>()
+                    )
+                } else {
+                    quote
+                };
+                ItemBuilder::default()
+                    .title(Some(title))
+                    .link(Some(annotation.incontext.to_owned()))
+                    .description(Some(annotation.annotation.text.to_owned()))
+                    .pub_date(Some(annotation.annotation.created.to_rfc2822()))
+                    .guid(Some(
+                        GuidBuilder::default()
+                            .value(annotation.annotation.id.to_owned())
+                            .permalink(false)
+                            .build(),
+                    ))
+                    .categories(
+                        annotation
+                            .annotation
+                            .tags
+                            .iter()
+                            .map(|tag| CategoryBuilder::default().name(tag.to_owned()).build())
+                            .collect::<Vec<_>>(),
+                    )
+                    .build()
+            })
+            .collect();
+        let username = self
+            .config
+            .hypothesis_username
+            .as_deref()
+            .unwrap_or_default();
+        let channel = ChannelBuilder::default()
+            .title(
+                self.config
+                    .feed_title
+                    .clone()
+                    .unwrap_or_else(|| format!("{}'s annotations", username)),
+            )
+            .link(format!("https://hypothes.is/users/{}", username))
+            .description(
+                self.config
+                    .feed_description
+                    .clone()
+                    .unwrap_or_else(|| "Hypothesis annotations exported by gooseberry".to_owned()),
+            )
+            .items(items)
+            .build();
+        let mut writer = Self::export_writer(output)?;
+        channel.write_to(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Write annotations as Logseq blocks into the `pages` (split by source)
+    /// or `journals` (split by day) folder of a Logseq graph
+    fn export_logseq(
+        &self,
+        annotations: Vec<Annotation>,
+        split: ExportSplit,
+        output: &Path,
+        force: bool,
+    ) -> color_eyre::Result<()> {
+        let mut files: BTreeMap<PathBuf, Vec<AnnotationTemplate>> = BTreeMap::new();
+        for annotation in annotations {
+            let path = match split {
+                ExportSplit::Source => output
+                    .join("pages")
+                    .join(format!("{}.md", uri_to_filename(&annotation.uri))),
+                ExportSplit::Day => output
+                    .join("journals")
+                    .join(format!("{}.md", annotation.created.format("%Y_%m_%d"))),
+            };
+            files
+                .entry(path)
+                .or_default()
+                .push(AnnotationTemplate::from_annotation(
+                    annotation,
+                    &self.config.hypothesis_groups,
+                ));
+        }
+        if files.is_empty() {
+            println!("No matching annotations");
+            return Ok(());
+        }
+        let num_existing = files.keys().filter(|path| path.exists()).count();
+        if num_existing > 0
+            && !force
+            && !utils::confirm(
+                &format!("Overwrite {} existing file(s)?", num_existing),
+                false,
+                "Use --force to overwrite them",
+            )?
+        {
+            let error: color_eyre::Result<()> = Err(Apologize::DoingNothing.into());
+            return error.suggestion("Export to an empty directory or use --force");
+        }
+        for (path, annotations) in &files {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut content = String::new();
+            if split == ExportSplit::Source {
+                // Page properties
+                content.push_str(&format!(
+                    "title:: {}\nsource:: {}\n\n",
+                    annotations[0].title, annotations[0].annotation.uri
+                ));
+            }
+            content.push_str(
+                &annotations
+                    .iter()
+                    .map(logseq_block)
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            );
+            content.push('\n');
+            fs::File::create(path)?.write_all(content.as_bytes())?;
+        }
+        println!("Exported {} file(s) to {:?}", files.len(), output);
+        Ok(())
+    }
+}
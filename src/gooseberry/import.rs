@@ -0,0 +1,198 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use color_eyre::Help;
+use hypothesis::annotations::{Annotation, SearchQuery};
+use serde::Deserialize;
+
+use crate::errors::Apologize;
+use crate::gooseberry::{to_input_annotation, Gooseberry};
+use crate::utils;
+
+/// Most bytes of annotation JSON read from a URL
+const IMPORT_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// Annotation JSON at `url`, fetched with a timeout and at most `IMPORT_MAX_BYTES` long
+async fn fetch_import(url: &str) -> color_eyre::Result<String> {
+    let client = reqwest::Client::builder()
+        .timeout(utils::API_TIMEOUT)
+        .build()?;
+    let mut response = client.get(url).send().await?.error_for_status()?;
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+        if body.len() > IMPORT_MAX_BYTES {
+            let error: color_eyre::Result<String> = Err(Apologize::ImportError {
+                message: format!(
+                    "{} is larger than {} MiB",
+                    url,
+                    IMPORT_MAX_BYTES / 1024 / 1024
+                ),
+            }
+            .into());
+            return error.suggestion("Download the JSON and split it into smaller files");
+        }
+    }
+    Ok(String::from_utf8(body)?)
+}
+
+/// Annotations with the same URI, text, and target selectors count as the same annotation when importing
+fn import_key(annotation: &Annotation) -> color_eyre::Result<(String, String, String)> {
+    let selectors = annotation
+        .target
+        .iter()
+        .flat_map(|target| &target.selector)
+        .collect::<Vec<_>>();
+    Ok((
+        annotation.uri.to_owned(),
+        annotation.text.to_owned(),
+        serde_json::to_string(&selectors)?,
+    ))
+}
+
+/// Annotation JSON as exported by Hypothesis or other tools
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum AnnotationExport {
+    /// A list of annotations
+    List(Vec<Annotation>),
+    /// A Hypothesis API search response
+    Search { rows: Vec<Annotation> },
+}
+
+impl AnnotationExport {
+    fn parse(content: &str) -> color_eyre::Result<Vec<Annotation>> {
+        let export: color_eyre::Result<Self> = serde_json::from_str(content).map_err(|e| {
+            Apologize::ImportError {
+                message: e.to_string(),
+            }
+            .into()
+        });
+        let export = export.suggestion(
+            "Expected a JSON list of annotations or a Hypothesis search response with \"rows\"",
+        )?;
+        Ok(match export {
+            AnnotationExport::List(annotations) => annotations,
+            AnnotationExport::Search { rows } => rows,
+        })
+    }
+}
+
+/// ## Import
+/// Creating annotations from annotation JSON made elsewhere
+impl Gooseberry {
+    /// Import annotations from a JSON file or URL into one of gooseberry's groups.
+    /// Annotations that are already in the group (or twice in the JSON) are only created once
+    pub async fn import(
+        &self,
+        file: Option<&Path>,
+        url: Option<&str>,
+        to: Option<&str>,
+        no_default_tags: bool,
+        local: bool,
+    ) -> color_eyre::Result<()> {
+        let content = match (file, url) {
+            (Some(file), _) => fs::read_to_string(file)?,
+            (None, Some(url)) => fetch_import(url).await?,
+            (None, None) => {
+                let error: color_eyre::Result<()> = Err(Apologize::ImportError {
+                    message: "Nothing to import".into(),
+                }
+                .into());
+                return error.suggestion("Give a JSON file or use --url <url>");
+            }
+        };
+        let annotations = AnnotationExport::parse(&content)?;
+        if local {
+            return self.import_local(annotations);
+        }
+        let group_id = self.get_move_target(to)?;
+        let num_found = annotations.len();
+        let annotations = annotations
+            .into_iter()
+            .filter(|annotation| !annotation.uri.is_empty())
+            .collect::<Vec<_>>();
+        let num_without_uri = num_found - annotations.len();
+        let mut existing = self.existing_import_keys(&annotations, &group_id).await?;
+        let mut new_annotations = Vec::with_capacity(annotations.len());
+        for annotation in annotations {
+            if existing.insert(import_key(&annotation)?) {
+                new_annotations.push(annotation);
+            }
+        }
+        let num_existing = num_found - num_without_uri - new_annotations.len();
+        let input_annotations = new_annotations
+            .into_iter()
+            .map(|mut annotation| {
+                if !no_default_tags {
+                    self.add_default_tags(&mut annotation.tags);
+                }
+                to_input_annotation(annotation, &group_id)
+            })
+            .collect::<color_eyre::Result<Vec<_>>>()?;
+        let mut skipped = Vec::new();
+        if num_without_uri > 0 {
+            skipped.push(format!("skipped {} without a URI", num_without_uri));
+        }
+        if num_existing > 0 {
+            skipped.push(format!(
+                "skipped {} already in the group or listed twice",
+                num_existing
+            ));
+        }
+        let skipped = skipped
+            .into_iter()
+            .map(|skipped| format!(", {}", skipped))
+            .collect::<String>();
+        if input_annotations.is_empty() {
+            println!("No annotations to import{}", skipped);
+            return Ok(());
+        }
+        self.api.create_annotations(&input_annotations).await?;
+        println!(
+            "Imported {} annotation(s){}",
+            input_annotations.len(),
+            skipped
+        );
+        self.sync().await?;
+        Ok(())
+    }
+
+    /// Import keys of your annotations in a group on the URIs of the annotations to import
+    async fn existing_import_keys(
+        &self,
+        annotations: &[Annotation],
+        group_id: &str,
+    ) -> color_eyre::Result<HashSet<(String, String, String)>> {
+        let uris = annotations
+            .iter()
+            .map(|annotation| annotation.uri.as_str())
+            .collect::<HashSet<_>>();
+        let spinner = utils::get_spinner("Checking for annotations already in the group...")?;
+        let mut keys = HashSet::new();
+        for uri in uris {
+            let mut query = SearchQuery::builder()
+                .limit(self.config.sync_limit())
+                .uri(uri)
+                .user(&self.api.user.0)
+                .group(vec![group_id.to_owned()])
+                .build()?;
+            for annotation in self.search_annotations(&mut query, &spinner).await? {
+                keys.insert(import_key(&annotation)?);
+            }
+        }
+        spinner.finish_and_clear();
+        Ok(keys)
+    }
+
+    /// Add annotations to the database as they are, without creating them on Hypothesis
+    fn import_local(&self, annotations: Vec<Annotation>) -> color_eyre::Result<()> {
+        let (added, updated) = self.sync_annotations(annotations)?;
+        println!(
+            "Added {} and updated {} annotation(s) in the local database",
+            added, updated
+        );
+        Ok(())
+    }
+}
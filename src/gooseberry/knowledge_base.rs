@@ -1,28 +1,31 @@
+use std::cell::RefCell;
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::process::Command;
 
 use chrono::{DateTime, Utc};
+use chrono_humanize::HumanTime;
+use chrono_tz::Tz;
 use color_eyre::Help;
-use dialoguer::theme::ColorfulTheme;
-use dialoguer::Confirm;
 use eyre::eyre;
-use handlebars::{Handlebars, RenderError};
+use handlebars::{Context, Handlebars, Helper, HelperDef, RenderContext, RenderError, ScopedJson};
 use hypothesis::annotations::Annotation;
-use sanitize_filename::sanitize;
 use serde::Serialize;
 use serde_json::Value as Json;
 use url::Url;
 
 use crate::configuration::{
-    OrderBy, DEFAULT_ANNOTATION_TEMPLATE, DEFAULT_INDEX_LINK_TEMPLATE, DEFAULT_PAGE_TEMPLATE,
+    LinkStyle, OrderBy, OrphanedReplies, SectionHeader, DEFAULT_ANNOTATION_TEMPLATE,
+    DEFAULT_INDEX_LINK_TEMPLATE, DEFAULT_MAX_FILENAME_LENGTH, DEFAULT_PAGE_TEMPLATE,
+    DEFAULT_RECENT_TEMPLATE, DEFAULT_WRAP_WIDTH,
 };
 use crate::errors::Apologize;
 use crate::gooseberry::Gooseberry;
 use crate::utils;
-use crate::utils::{clean_uri, uri_to_filename};
+use crate::utils::{clean_uri, to_filename, uri_to_filename};
 use crate::EMPTY_TAG;
 
 /// To convert an annotation to text
@@ -31,11 +34,79 @@ pub struct AnnotationTemplate {
     #[serde(flatten)]
     pub annotation: Annotation,
     pub base_uri: String,
+    /// Registrable domain of the URI, e.g. "wikipedia.org" for all of Wikipedia's subdomains
+    pub domain: String,
     pub title: String,
     pub incontext: String,
+    /// HTML anchor for the annotation, the same across rebuilds since it's made from the ID
+    pub anchor: String,
+    /// Link to the first quote in the live page, using a text fragment (`#:~:text=`)
+    pub text_fragment_url: Option<String>,
     pub highlight: Vec<String>,
+    /// Language of quoted code, from a `lang:<language>` tag or the file extension in the URI
+    pub quote_language: Option<String>,
     pub display_name: Option<String>,
+    /// Username of the annotation creator, from their account ID
+    pub username: String,
     pub group_name: String,
+    /// Whether this is a reply to an annotation that isn't synced
+    pub missing_parent: bool,
+    /// The annotation this is a reply to, if it's synced
+    pub parent: Option<Annotation>,
+    /// Synced direct replies to this annotation, oldest first
+    pub replies: Vec<Annotation>,
+}
+
+/// File extensions of source code and the languages to use in code block fences
+static CODE_EXTENSIONS: &[(&str, &str)] = &[
+    ("rs", "rust"),
+    ("py", "python"),
+    ("js", "javascript"),
+    ("ts", "typescript"),
+    ("go", "go"),
+    ("java", "java"),
+    ("kt", "kotlin"),
+    ("c", "c"),
+    ("h", "c"),
+    ("cpp", "cpp"),
+    ("hpp", "cpp"),
+    ("cs", "csharp"),
+    ("rb", "ruby"),
+    ("php", "php"),
+    ("swift", "swift"),
+    ("scala", "scala"),
+    ("hs", "haskell"),
+    ("jl", "julia"),
+    ("r", "r"),
+    ("sh", "bash"),
+    ("sql", "sql"),
+    ("toml", "toml"),
+    ("yaml", "yaml"),
+    ("yml", "yaml"),
+    ("json", "json"),
+];
+
+/// Language of the quoted text if it's code,
+/// taken from a `lang:<language>` tag or else from the file extension of the annotated URI
+fn quote_language(annotation: &Annotation) -> Option<String> {
+    if let Some(language) = annotation
+        .tags
+        .iter()
+        .find_map(|tag| tag.strip_prefix("lang:"))
+    {
+        return Some(language.to_owned());
+    }
+    let uri = Url::parse(&annotation.uri).ok()?;
+    let extension = uri
+        .path_segments()?
+        .last()?
+        .rsplit_once('.')?
+        .1
+        .to_lowercase();
+    CODE_EXTENSIONS
+        .iter()
+        .find(|(ext, _)| *ext == extension)
+        .map(|(_, language)| (*language).to_owned())
 }
 
 pub fn replace_spaces(astring: &str) -> String {
@@ -52,26 +123,35 @@ impl AnnotationTemplate {
         } else {
             annotation.uri.to_string()
         };
+        let domain = utils::registrable_domain(&annotation.uri);
         let incontext = annotation
             .links
             .get("incontext")
             .unwrap_or(&annotation.uri)
             .to_owned();
-        let highlight = utils::get_quotes(&annotation)
+        let highlight: Vec<String> = utils::get_quotes(&annotation)
             .into_iter()
             .map(|s| s.to_owned())
             .collect();
+        let text_fragment_url = highlight
+            .first()
+            .map(|quote| quote.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|quote| !quote.is_empty() && !annotation.uri.starts_with("urn:"))
+            .map(|quote| {
+                let uri = annotation.uri.split('#').next().unwrap_or_default();
+                // Dashes delimit prefixes and suffixes in text fragments
+                let quote = urlencoding::encode(&quote).replace('-', "%2D");
+                format!("{}#:~:text={}", uri, quote)
+            });
+        let quote_language = quote_language(&annotation);
+        let anchor = format!("annotation-{}", annotation.id);
+        let username = utils::username(&annotation.user.0).to_owned();
         let display_name = if let Some(user_info) = &annotation.user_info {
             user_info.display_name.clone()
         } else {
             None
         };
-        let mut title = String::from("Untitled document");
-        if let Some(document) = &annotation.document {
-            if !document.title.is_empty() {
-                title = document.title[0].to_owned();
-            }
-        }
+        let title = utils::get_title(&annotation).to_owned();
         let group_name = hypothesis_groups
             .get(&annotation.group)
             .unwrap_or(&annotation.group)
@@ -79,30 +159,135 @@ impl AnnotationTemplate {
         AnnotationTemplate {
             annotation,
             base_uri,
+            domain,
             title,
             incontext,
+            anchor,
+            text_fragment_url,
             highlight,
+            quote_language,
             display_name,
+            username,
             group_name,
+            missing_parent: false,
+            parent: None,
+            replies: Vec::new(),
         }
     }
 }
 
+/// Formats a date in UTC, or in `timezone` if given
 pub(crate) fn format_date<E: AsRef<str>>(
     format: E,
     date: &Json,
+    timezone: Option<Tz>,
 ) -> Result<String, serde_json::Error> {
     let date: DateTime<Utc> = serde_json::from_value(date.clone())?;
-    Ok(format!("{}", date.format(format.as_ref())))
+    Ok(match timezone {
+        Some(timezone) => format!("{}", date.with_timezone(&timezone).format(format.as_ref())),
+        None => format!("{}", date.format(format.as_ref())),
+    })
+}
+
+/// Handlebars helper formatting a date, in UTC or in the IANA timezone given as a third parameter
+/// e.g. `{{date_format "%c" created "America/New_York"}}`
+struct DateFormat;
+
+impl HelperDef for DateFormat {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let format = h
+            .param(0)
+            .and_then(|format| format.value().as_str())
+            .ok_or_else(|| RenderError::new("date_format expects a format string"))?;
+        let date = h
+            .param(1)
+            .ok_or_else(|| RenderError::new("date_format expects a date"))?
+            .value();
+        let timezone = match h.param(2) {
+            Some(timezone) => {
+                let timezone = timezone
+                    .value()
+                    .as_str()
+                    .ok_or_else(|| RenderError::new("date_format expects a timezone name"))?;
+                Some(timezone.parse::<Tz>().map_err(|e| {
+                    RenderError::new(format!("Unknown timezone {:?}: {}", timezone, e))
+                })?)
+            }
+            None => None,
+        };
+        let formatted = format_date(format, date, timezone)
+            .map_err(|e| RenderError::from_error("serde_json", e))?;
+        Ok(ScopedJson::Derived(Json::String(formatted)))
+    }
 }
 
-handlebars_helper!(date_format: |format: str, date: Json| format_date(format, date).map_err(|e| RenderError::from_error("serde_json", e))?);
+/// Date relative to now, e.g. "3 days ago" or "in a month"
+pub(crate) fn format_relative_date(date: &Json) -> Result<String, serde_json::Error> {
+    let date: DateTime<Utc> = serde_json::from_value(date.clone())?;
+    Ok(HumanTime::from(date).to_string())
+}
+
+/// Cuts text to at most `length` characters, at the last word boundary, followed by an ellipsis
+pub(crate) fn truncate_text(text: &str, length: usize) -> String {
+    if text.chars().count() <= length {
+        return text.to_owned();
+    }
+    let cut: String = text.chars().take(length).collect();
+    // Keep a word that ends exactly at the cut
+    let next_is_space = text.chars().nth(length).is_some_and(char::is_whitespace);
+    let truncated = match cut.rfind(char::is_whitespace) {
+        Some(end) if !next_is_space => &cut[..end],
+        _ => &cut,
+    };
+    format!("{}…", truncated.trim_end())
+}
+
+/// Converts text to a slug for anchors and links: lowercase, with each run of characters
+/// that aren't letters or digits replaced by a single hyphen, e.g. "Rust/Tips: 2" becomes "rust-tips-2"
+pub(crate) fn slugify_text(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    if slug.ends_with('-') {
+        slug.pop();
+    }
+    slug
+}
+
+handlebars_helper!(relative_date: |date: Json| format_relative_date(date).map_err(|e| RenderError::from_error("serde_json", e))?);
 handlebars_helper!(url_encode: |s: str| {urlencoding::encode(s).to_string()});
+handlebars_helper!(truncate: |text: str, length: u64| truncate_text(text, length as usize));
+handlebars_helper!(slugify: |text: str| slugify_text(text));
+// A JSON string is also a valid double-quoted YAML string
+handlebars_helper!(yaml_string: |text: str| serde_json::to_string(text).map_err(|e| RenderError::from_error("serde_json", e))?);
 
 pub(crate) struct Templates<'a> {
     pub(crate) annotation_template: &'a str,
     pub(crate) page_template: &'a str,
     pub(crate) index_link_template: &'a str,
+    pub(crate) human_index_link_template: &'a str,
+    pub(crate) recent_template: &'a str,
+    /// Header before the index links of each group of the first hierarchy level, if set
+    pub(crate) index_section_template: Option<&'a str>,
+    /// YAML frontmatter fields at the start of each page, if set
+    pub(crate) page_frontmatter_template: Option<&'a str>,
+    /// Extra annotation templates as (tag prefix, template) pairs
+    pub(crate) annotation_templates: Vec<(&'a str, &'a str)>,
+    /// Icons to show instead of tags, used by the `tag_icon` helper
+    pub(crate) tag_icons: HashMap<String, String>,
+    /// Line width used by the `wrap` helper
+    pub(crate) wrap_width: usize,
 }
 
 impl<'a> Default for Templates<'a> {
@@ -111,55 +296,447 @@ impl<'a> Default for Templates<'a> {
             annotation_template: DEFAULT_ANNOTATION_TEMPLATE,
             page_template: DEFAULT_PAGE_TEMPLATE,
             index_link_template: DEFAULT_INDEX_LINK_TEMPLATE,
+            human_index_link_template: DEFAULT_INDEX_LINK_TEMPLATE,
+            recent_template: DEFAULT_RECENT_TEMPLATE,
+            index_section_template: None,
+            page_frontmatter_template: None,
+            annotation_templates: Vec::new(),
+            tag_icons: HashMap::new(),
+            wrap_width: DEFAULT_WRAP_WIDTH,
+        }
+    }
+}
+
+/// Handlebars helper mapping a tag to its configured icon, or to the tag itself if it has none
+struct TagIcon(HashMap<String, String>);
+
+impl HelperDef for TagIcon {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let tag = h
+            .param(0)
+            .and_then(|tag| tag.value().as_str())
+            .ok_or_else(|| RenderError::new("tag_icon expects a tag"))?;
+        Ok(ScopedJson::Derived(Json::String(
+            self.0.get(tag).cloned().unwrap_or_else(|| tag.to_owned()),
+        )))
+    }
+}
+
+/// Handlebars helper making an Obsidian-style `[[target]]` link, or `[[target|alias]]` with a second parameter
+struct Wikilink;
+
+impl HelperDef for Wikilink {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let target = h
+            .param(0)
+            .and_then(|target| target.value().as_str())
+            .ok_or_else(|| RenderError::new("wikilink expects a link target"))?;
+        let link = match h.param(1).and_then(|alias| alias.value().as_str()) {
+            Some(alias) => format!("[[{}|{}]]", target, alias),
+            None => format!("[[{}]]", target),
+        };
+        Ok(ScopedJson::Derived(Json::String(link)))
+    }
+}
+
+/// Rewraps each line of markdown text to `width`.
+///
+/// Existing line breaks are kept, code blocks are left alone,
+/// and wrapped quote lines keep their `>` prefix.
+fn wrap_text(text: &str, width: usize) -> String {
+    let mut in_code_block = false;
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_code_block = !in_code_block;
+            lines.push(line.to_owned());
+        } else if in_code_block || line.trim().is_empty() {
+            lines.push(line.to_owned());
+        } else {
+            let content_start = line
+                .find(|c: char| c != '>' && !c.is_whitespace())
+                .unwrap_or(0);
+            let (prefix, content) = line.split_at(content_start);
+            let options = textwrap::Options::new(width)
+                .initial_indent(prefix)
+                .subsequent_indent(prefix);
+            lines.extend(
+                textwrap::wrap(content, options)
+                    .into_iter()
+                    .map(|l| l.into_owned()),
+            );
         }
     }
+    lines.join("\n")
+}
+
+/// Handlebars helper wrapping text to the configured width, or to the width given as a second parameter
+struct Wrap(usize);
+
+impl HelperDef for Wrap {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let text = h
+            .param(0)
+            .and_then(|text| text.value().as_str())
+            .ok_or_else(|| RenderError::new("wrap expects a string"))?;
+        let width = h
+            .param(1)
+            .and_then(|width| width.value().as_u64())
+            .map_or(self.0, |width| width as usize);
+        Ok(ScopedJson::Derived(Json::String(wrap_text(text, width))))
+    }
 }
 
 pub(crate) fn get_handlebars(templates: Templates) -> color_eyre::Result<Handlebars> {
     let mut hbs = Handlebars::new();
     handlebars_misc_helpers::register(&mut hbs);
     hbs.register_escape_fn(handlebars::no_escape);
-    hbs.register_helper("date_format", Box::new(date_format));
+    hbs.register_helper("date_format", Box::new(DateFormat));
+    hbs.register_helper("relative_date", Box::new(relative_date));
     hbs.register_helper("url_encode", Box::new(url_encode));
+    hbs.register_helper("truncate", Box::new(truncate));
+    hbs.register_helper("slugify", Box::new(slugify));
+    hbs.register_helper("yaml_string", Box::new(yaml_string));
+    hbs.register_helper("tag_icon", Box::new(TagIcon(templates.tag_icons)));
+    hbs.register_helper("wrap", Box::new(Wrap(templates.wrap_width)));
+    hbs.register_helper("wikilink", Box::new(Wikilink));
     hbs.register_template_string("annotation", templates.annotation_template)?;
     hbs.register_template_string("page", templates.page_template)?;
     hbs.register_template_string("index_link", templates.index_link_template)?;
+    hbs.register_template_string("human_index_link", templates.human_index_link_template)?;
+    hbs.register_template_string("recent", templates.recent_template)?;
+    if let Some(index_section_template) = templates.index_section_template {
+        hbs.register_template_string("index_section", index_section_template)?;
+    }
+    if let Some(page_frontmatter_template) = templates.page_frontmatter_template {
+        hbs.register_template_string("page_frontmatter", page_frontmatter_template)?;
+    }
+    for (tag_prefix, template) in templates.annotation_templates {
+        hbs.register_template_string(&annotation_template_name(tag_prefix), template)?;
+    }
     Ok(hbs)
 }
 
+/// Mermaid graph of tags, with the number of annotations per tag,
+/// and edges between tags used together with the number of annotations they share
+fn make_tag_graph(annotations: &[AnnotationTemplate]) -> String {
+    let mut tag_counts: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut edge_counts: BTreeMap<(&str, &str), usize> = BTreeMap::new();
+    for annotation in annotations {
+        let mut tags: Vec<&str> = annotation
+            .annotation
+            .tags
+            .iter()
+            .map(String::as_str)
+            .filter(|tag| !tag.is_empty() && *tag != EMPTY_TAG)
+            .collect();
+        tags.sort_unstable();
+        tags.dedup();
+        for (i, tag) in tags.iter().enumerate() {
+            *tag_counts.entry(tag).or_default() += 1;
+            for other in &tags[i + 1..] {
+                *edge_counts.entry((tag, other)).or_default() += 1;
+            }
+        }
+    }
+    // Tags can have characters mermaid doesn't allow in node IDs, so they're numbered
+    let node_ids: HashMap<&str, usize> = tag_counts
+        .keys()
+        .enumerate()
+        .map(|(i, tag)| (*tag, i))
+        .collect();
+    let mut graph = String::from("# Tags\n\n```mermaid\ngraph TD\n");
+    for (tag, count) in &tag_counts {
+        graph.push_str(&format!(
+            "    tag{}[\"{} ({})\"]\n",
+            node_ids[tag],
+            tag.replace('"', "#quot;"),
+            count
+        ));
+    }
+    for ((tag, other), count) in &edge_counts {
+        graph.push_str(&format!(
+            "    tag{} ---|{}| tag{}\n",
+            node_ids[tag], count, node_ids[other]
+        ));
+    }
+    graph.push_str("```\n");
+    graph
+}
+
+/// Runs git with the given arguments inside `dir`
+fn git(dir: &Path, args: &[&str]) -> color_eyre::Result<std::process::Output> {
+    Ok(Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .suggestion("Make sure git is installed to use git_autocommit")?)
+}
+
+/// Commits all changes in the knowledge base directory.
+/// Skips (with a warning) if the directory isn't in a git repository.
+fn git_autocommit(kb_dir: &Path) -> color_eyre::Result<()> {
+    if !git(kb_dir, &["rev-parse", "--is-inside-work-tree"])?
+        .status
+        .success()
+    {
+        eprintln!(
+            "Warning: {:?} is not a git repository, skipping git_autocommit",
+            kb_dir
+        );
+        return Ok(());
+    }
+    // Only stage and commit changes within the knowledge base directory
+    let output = git(kb_dir, &["add", "-A", "--", "."])?;
+    if !output.status.success() {
+        return Err(Apologize::KBError {
+            message: format!(
+                "git add failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        }
+        .into());
+    }
+    if git(kb_dir, &["diff", "--cached", "--quiet", "--", "."])?
+        .status
+        .success()
+    {
+        println!("No knowledge base changes to commit");
+        return Ok(());
+    }
+    let message = format!("gooseberry sync {}", Utc::now().format("%F %T"));
+    let output = git(kb_dir, &["commit", "-m", &message, "--", "."])?;
+    if !output.status.success() {
+        return Err(Apologize::KBError {
+            message: format!(
+                "git commit failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        }
+        .into());
+    }
+    println!("Committed knowledge base changes: {}", message);
+    Ok(())
+}
+
+/// Collects the files with the given extension in `dir` and its subfolders,
+/// skipping hidden ones like `.git`
+fn files_with_extension(
+    dir: &Path,
+    extension: &str,
+    files: &mut Vec<PathBuf>,
+) -> color_eyre::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+        {
+            continue;
+        }
+        if path.is_dir() {
+            files_with_extension(&path, extension, files)?;
+        } else if path.extension().is_some_and(|e| e == extension) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Name under which the annotation template for a tag prefix is registered
+pub(crate) fn annotation_template_name(tag_prefix: &str) -> String {
+    format!("annotation:{tag_prefix}")
+}
+
 /// To convert an annotation to text
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct LinkTemplate {
     pub name: String,
     pub relative_path: String,
     pub absolute_path: String,
+    /// Number of folders the page is nested in, within the knowledge base directory
+    pub depth: usize,
+    /// Two spaces per level of `depth`, to indent nested links in the index
+    pub indent: String,
 }
 
-fn get_link_data(path: &Path, src_dir: &Path) -> color_eyre::Result<LinkTemplate> {
+/// Link data for the page at `path`, with spaces in its paths encoded as `%20` if `encode_spaces` is set
+/// (wikilinks use the paths as they are)
+fn get_link_data(
+    path: &Path,
+    src_dir: &Path,
+    encode_spaces: bool,
+) -> color_eyre::Result<LinkTemplate> {
+    let depth = path
+        .strip_prefix(src_dir)?
+        .components()
+        .count()
+        .saturating_sub(1);
+    let to_link = |path: &Path| -> color_eyre::Result<String> {
+        let link = path.to_str().ok_or(Apologize::KBError {
+            message: format!("{:?} has non-unicode characters", path),
+        })?;
+        Ok(if encode_spaces {
+            link.replace(' ', "%20")
+        } else {
+            link.to_owned()
+        })
+    };
     Ok(LinkTemplate {
         name: path
             .file_stem()
             .unwrap_or_else(|| "EMPTY".as_ref())
             .to_string_lossy()
             .to_string(),
-        relative_path: path
-            .strip_prefix(src_dir)?
-            .to_str()
-            .ok_or(Apologize::KBError {
-                message: format!("{:?} has non-unicode characters", path),
-            })?
-            .to_string()
-            .replace(' ', "%20"),
-        absolute_path: path
-            .to_str()
-            .ok_or(Apologize::KBError {
-                message: format!("{:?} has non-unicode characters", path),
-            })?
-            .to_string()
-            .replace(' ', "%20"),
+        relative_path: to_link(path.strip_prefix(src_dir)?)?,
+        absolute_path: to_link(path)?,
+        depth,
+        indent: "  ".repeat(depth),
     })
 }
 
+/// A link to a page in the knowledge base, with the IDs of the annotations on it
+struct IndexLink {
+    link_data: LinkTemplate,
+    annotation_ids: Vec<String>,
+    /// Name of the group of the first hierarchy level the page is in
+    section: String,
+}
+
+/// What `make_book` does with the rendered knowledge base
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BuildMode {
+    /// Write the files
+    Write,
+    /// Only render, collecting all render errors and filename collisions
+    Check,
+    /// Only render, comparing with the files on disk
+    Drift,
+}
+
+/// Collects render errors and filename collisions when checking the knowledge base,
+/// instead of stopping at the first error, and files that differ from the rendered ones
+struct BookChecker {
+    mode: BuildMode,
+    check: bool,
+    errors: RefCell<Vec<String>>,
+    paths: RefCell<HashSet<String>>,
+    stale: RefCell<Vec<PathBuf>>,
+    rendered: RefCell<HashSet<PathBuf>>,
+    names: RefCell<HashSet<String>>,
+}
+
+impl BookChecker {
+    fn new(mode: BuildMode) -> Self {
+        Self {
+            mode,
+            check: mode == BuildMode::Check,
+            errors: RefCell::new(Vec::new()),
+            paths: RefCell::new(HashSet::new()),
+            stale: RefCell::new(Vec::new()),
+            rendered: RefCell::new(HashSet::new()),
+            names: RefCell::new(HashSet::new()),
+        }
+    }
+
+    /// Cuts a name to at most `length` characters. A counter is appended if the name, cut or not,
+    /// was already used, e.g. for two long tags that only differ after the first `length` characters,
+    /// or for a name that fits but is the same as an earlier name that was cut and given a counter.
+    /// Other names that fit are kept as they are.
+    fn truncate(&self, name: &str, length: usize) -> String {
+        let mut used = self.names.borrow_mut();
+        let mut truncated: String = name.chars().take(length).collect();
+        let mut counter = 1;
+        // Ignoring case, for case-insensitive file systems
+        while !used.insert(truncated.to_lowercase()) {
+            counter += 1;
+            let suffix = format!(" ({})", counter);
+            truncated = name
+                .chars()
+                .take(length.saturating_sub(suffix.len()))
+                .chain(suffix.chars())
+                .collect();
+        }
+        truncated
+    }
+
+    /// Writes a file, or in drift mode records it if it's missing or differs from `contents`
+    fn write(&self, path: &Path, contents: &str) -> color_eyre::Result<()> {
+        match self.mode {
+            BuildMode::Write => fs::File::create(path)?.write_all(contents.as_bytes())?,
+            BuildMode::Check => (),
+            BuildMode::Drift => {
+                if !fs::read_to_string(path).is_ok_and(|on_disk| on_disk == contents) {
+                    self.stale.borrow_mut().push(path.to_owned());
+                }
+                self.rendered.borrow_mut().insert(path.to_owned());
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the rendered value, in check mode errors are recorded and `None` is returned
+    fn catch<T>(
+        &self,
+        result: Result<T, RenderError>,
+        context: impl FnOnce() -> String,
+    ) -> Result<Option<T>, RenderError> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if self.check => {
+                self.errors
+                    .borrow_mut()
+                    .push(format!("{}: {}", context(), e));
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Records a collision if a file path was already used (ignoring case, for case-insensitive file systems)
+    fn check_path(&self, path: &Path) {
+        if self.check
+            && !self
+                .paths
+                .borrow_mut()
+                .insert(path.to_string_lossy().to_lowercase())
+        {
+            self.errors
+                .borrow_mut()
+                .push(format!("{:?}: filename collision", path));
+        }
+    }
+}
+
+/// To render an entry of the recently updated section of the index
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RecentTemplate {
+    #[serde(flatten)]
+    pub link_data: LinkTemplate,
+    #[serde(flatten)]
+    pub annotation: AnnotationTemplate,
+}
+
 /// To convert an annotation to text
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PageTemplate {
@@ -169,10 +746,20 @@ pub struct PageTemplate {
     pub raw_annotations: Vec<AnnotationTemplate>,
 }
 
+/// Friendly name from `uri_aliases` for a URI, ignoring trailing slashes
+fn uri_alias<'a>(uri: &str, uri_aliases: &'a HashMap<String, String>) -> Option<&'a str> {
+    let uri = uri.trim_end_matches('/');
+    uri_aliases
+        .iter()
+        .find(|(aliased_uri, _)| aliased_uri.trim_end_matches('/') == uri)
+        .map(|(_, alias)| alias.as_str())
+}
+
 fn group_annotations_by_order(
     order: OrderBy,
     annotations: Vec<AnnotationTemplate>,
     nested_tag: Option<&String>,
+    uri_aliases: &HashMap<String, String>,
 ) -> HashMap<String, Vec<AnnotationTemplate>> {
     let mut order_to_annotations = HashMap::new();
     match order {
@@ -186,10 +773,15 @@ fn group_annotations_by_order(
                         .push(annotation);
                 } else {
                     for tag in &annotation.annotation.tags {
-                        let mut tag = tag.to_owned();
-                        if let Some(nested_tag) = nested_tag {
-                            tag = tag.replace(nested_tag, path_separator);
-                        }
+                        // Each level of a nested tag is a folder
+                        let tag = match nested_tag {
+                            Some(nested_tag) => tag
+                                .split(nested_tag.as_str())
+                                .map(to_filename)
+                                .collect::<Vec<_>>()
+                                .join(path_separator),
+                            None => to_filename(tag),
+                        };
                         order_to_annotations
                             .entry(tag)
                             .or_insert_with(Vec::new)
@@ -201,7 +793,12 @@ fn group_annotations_by_order(
         OrderBy::URI => {
             for annotation in annotations {
                 order_to_annotations
-                    .entry(uri_to_filename(&annotation.annotation.uri))
+                    .entry(to_filename(
+                        &uri_alias(&annotation.annotation.uri, uri_aliases).map_or_else(
+                            || uri_to_filename(&annotation.annotation.uri),
+                            ToOwned::to_owned,
+                        ),
+                    ))
                     .or_insert_with(Vec::new)
                     .push(annotation);
             }
@@ -209,7 +806,7 @@ fn group_annotations_by_order(
         OrderBy::Title => {
             for annotation in annotations {
                 order_to_annotations
-                    .entry(sanitize(&annotation.title))
+                    .entry(to_filename(&annotation.title))
                     .or_insert_with(Vec::new)
                     .push(annotation);
             }
@@ -217,7 +814,20 @@ fn group_annotations_by_order(
         OrderBy::BaseURI => {
             for annotation in annotations {
                 order_to_annotations
-                    .entry(uri_to_filename(&annotation.base_uri))
+                    .entry(to_filename(
+                        &uri_alias(&annotation.base_uri, uri_aliases).map_or_else(
+                            || uri_to_filename(&annotation.base_uri),
+                            ToOwned::to_owned,
+                        ),
+                    ))
+                    .or_insert_with(Vec::new)
+                    .push(annotation);
+            }
+        }
+        OrderBy::Domain => {
+            for annotation in annotations {
+                order_to_annotations
+                    .entry(to_filename(&annotation.domain))
                     .or_insert_with(Vec::new)
                     .push(annotation);
             }
@@ -225,7 +835,7 @@ fn group_annotations_by_order(
         OrderBy::ID => {
             for annotation in annotations {
                 order_to_annotations
-                    .entry(annotation.annotation.id.to_string())
+                    .entry(to_filename(&annotation.annotation.id))
                     .or_insert_with(Vec::new)
                     .push(annotation);
             }
@@ -233,7 +843,7 @@ fn group_annotations_by_order(
         OrderBy::Group => {
             for annotation in annotations {
                 order_to_annotations
-                    .entry(annotation.annotation.group.to_string())
+                    .entry(to_filename(&annotation.annotation.group))
                     .or_insert_with(Vec::new)
                     .push(annotation);
             }
@@ -241,7 +851,7 @@ fn group_annotations_by_order(
         OrderBy::GroupName => {
             for annotation in annotations {
                 order_to_annotations
-                    .entry(annotation.group_name.to_string())
+                    .entry(to_filename(&annotation.group_name))
                     .or_insert_with(Vec::new)
                     .push(annotation);
             }
@@ -252,7 +862,54 @@ fn group_annotations_by_order(
     order_to_annotations
 }
 
-fn sort_annotations(sort: &[OrderBy], annotations: &mut [AnnotationTemplate]) {
+/// Splits (sorted) annotations into sections of the single-file knowledge base, keeping their order within a section
+pub(crate) fn group_annotations_by_section(
+    section_header: SectionHeader,
+    annotations: &[AnnotationTemplate],
+) -> BTreeMap<String, Vec<AnnotationTemplate>> {
+    let mut sections: BTreeMap<String, Vec<AnnotationTemplate>> = BTreeMap::new();
+    for annotation in annotations {
+        match section_header {
+            SectionHeader::Date => sections
+                .entry(annotation.annotation.created.format("%F").to_string())
+                .or_default()
+                .push(annotation.clone()),
+            SectionHeader::Tag if annotation.annotation.tags.is_empty() => sections
+                .entry(EMPTY_TAG.to_owned())
+                .or_default()
+                .push(annotation.clone()),
+            SectionHeader::Tag => {
+                for tag in &annotation.annotation.tags {
+                    sections
+                        .entry(tag.to_owned())
+                        .or_default()
+                        .push(annotation.clone());
+                }
+            }
+        }
+    }
+    sections
+}
+
+/// Number of pages the hierarchy `order` makes from the annotations
+fn count_pages(
+    order: &[OrderBy],
+    annotations: Vec<AnnotationTemplate>,
+    nested_tag: Option<&String>,
+    uri_aliases: &HashMap<String, String>,
+) -> usize {
+    match order.split_first() {
+        None => 1,
+        Some((&field, order)) => {
+            group_annotations_by_order(field, annotations, nested_tag, uri_aliases)
+                .into_values()
+                .map(|annotations| count_pages(order, annotations, nested_tag, uri_aliases))
+                .sum()
+        }
+    }
+}
+
+pub(crate) fn sort_annotations(sort: &[OrderBy], annotations: &mut [AnnotationTemplate]) {
     annotations.sort_by(|a, b| {
         sort.iter().fold(Ordering::Equal, |acc, &field| {
             acc.then_with(|| match field {
@@ -263,6 +920,7 @@ fn sort_annotations(sort: &[OrderBy], annotations: &mut [AnnotationTemplate]) {
                     .cmp(&b.annotation.tags.join(",")),
                 OrderBy::URI => clean_uri(&a.annotation.uri).cmp(&clean_uri(&b.annotation.uri)),
                 OrderBy::BaseURI => clean_uri(&a.base_uri).cmp(&clean_uri(&b.base_uri)),
+                OrderBy::Domain => a.domain.cmp(&b.domain),
                 OrderBy::Title => a.title.cmp(&b.title),
                 OrderBy::ID => a.annotation.id.cmp(&b.annotation.id),
                 OrderBy::Created => format!("{}", a.annotation.created.format("%+"))
@@ -299,7 +957,7 @@ impl Gooseberry {
         Ok(())
     }
 
-    /// Make mdBook wiki
+    /// Make mdBook wiki, in `output_dir` instead of the configured directory if given
     pub fn make(
         &mut self,
         annotations: Vec<Annotation>,
@@ -307,39 +965,161 @@ impl Gooseberry {
         force: bool,
         make: bool,
         index: bool,
+        output_dir: Option<&Path>,
     ) -> color_eyre::Result<()> {
+        // Also sets up the hierarchy and templates, which --output-dir builds need too
         self.configure_kb()?;
-        let kb_dir = self
-            .config
-            .kb_dir
-            .as_ref()
-            .ok_or_else(|| eyre!("No knowledge base directory"))?;
+        let kb_dir = match output_dir {
+            Some(output_dir) => {
+                fs::create_dir_all(output_dir)?;
+                output_dir.to_path_buf()
+            }
+            None => self
+                .config
+                .kb_dir
+                .clone()
+                .ok_or_else(|| eyre!("No knowledge base directory"))?,
+        };
+        let kb_dir = &kb_dir;
         if clear
             && kb_dir.exists()
             && (force
-                || Confirm::with_theme(&ColorfulTheme::default())
-                    .with_prompt("Clear knowledge base directory?")
-                    .default(true)
-                    .interact()?)
+                || utils::confirm(
+                    "Clear knowledge base directory?",
+                    true,
+                    "Use --force to clear it without asking",
+                )?)
         {
             fs::remove_dir_all(kb_dir)?;
             fs::create_dir_all(kb_dir)?;
         }
-        self.make_book(annotations, kb_dir, make, index)?;
+        let ids: Vec<String> = annotations.iter().map(|a| a.id.to_owned()).collect();
+        self.make_book(annotations, kb_dir, make, index, BuildMode::Write)?;
+        // Written annotations count as reviewed, for the --stale-review filter
+        if make && output_dir.is_none() {
+            self.set_review_times(&ids, Utc::now())?;
+        }
+        // One-off builds to a different directory aren't committed
+        if make && output_dir.is_none() && self.config.git_autocommit {
+            git_autocommit(kb_dir)?;
+        }
+        Ok(())
+    }
+
+    /// Serve the knowledge base with `mdbook serve --open` if it's an mdBook (has a `book.toml`),
+    /// otherwise open the index file in the default app
+    pub fn open_kb(&self, output_dir: Option<&Path>) -> color_eyre::Result<()> {
+        let kb_dir = output_dir
+            .or(self.config.kb_dir.as_deref())
+            .ok_or_else(|| eyre!("No knowledge base directory"))?;
+        if kb_dir.join("book.toml").exists() {
+            let status = Command::new("mdbook")
+                .args(["serve", "--open"])
+                .current_dir(kb_dir)
+                .status()
+                .suggestion("Install mdBook (https://rust-lang.github.io/mdBook/) to serve the knowledge base")?;
+            if !status.success() {
+                return Err(Apologize::KBError {
+                    message: format!("mdbook serve failed with {}", status),
+                }
+                .into());
+            }
+        } else {
+            let index_file = kb_dir.join(format!(
+                "{}.{}",
+                self.config
+                    .index_name
+                    .as_ref()
+                    .ok_or_else(|| eyre!("No index name"))?,
+                self.config
+                    .file_extension
+                    .as_ref()
+                    .ok_or_else(|| eyre!("No file extension"))?
+            ));
+            webbrowser::open(&index_file.to_string_lossy())?;
+        }
         Ok(())
     }
+    /// Render the whole knowledge base and index without writing any files,
+    /// reporting all render errors and filename collisions
+    pub fn check_make(
+        &mut self,
+        annotations: Vec<Annotation>,
+        index: bool,
+    ) -> color_eyre::Result<()> {
+        self.configure_kb()?;
+        let kb_dir = self
+            .config
+            .kb_dir
+            .as_ref()
+            .ok_or_else(|| eyre!("No knowledge base directory"))?;
+        self.make_book(annotations, kb_dir, true, index, BuildMode::Check)
+    }
+
+    /// Render the whole knowledge base and index without writing any files,
+    /// reporting files that are missing or differ from the rendered ones
+    pub fn check_drift(
+        &mut self,
+        annotations: Vec<Annotation>,
+        index: bool,
+    ) -> color_eyre::Result<()> {
+        self.configure_kb()?;
+        let kb_dir = self
+            .config
+            .kb_dir
+            .as_ref()
+            .ok_or_else(|| eyre!("No knowledge base directory"))?;
+        self.make_book(annotations, kb_dir, true, index, BuildMode::Drift)
+    }
+
     /// Write markdown files for wiki
+    /// (or just render them, collecting all errors or comparing them to the files on disk)
     fn make_book(
         &self,
         annotations: Vec<Annotation>,
         src_dir: &Path,
         make: bool,
         index: bool,
+        mode: BuildMode,
     ) -> color_eyre::Result<()> {
+        let check = mode == BuildMode::Check;
         let mut annotations: Vec<_> = annotations
             .into_iter()
             .map(|a| AnnotationTemplate::from_annotation(a, &self.config.hypothesis_groups))
             .collect();
+        if self.config.skip_empty_annotations {
+            utils::skip_empty_annotations(&mut annotations, |a| &a.annotation);
+        }
+        // Thread context: the parent of each reply (unless it isn't synced) and direct replies
+        let mut replies: HashMap<String, Vec<Annotation>> = HashMap::new();
+        for reply in self.iter_annotations()? {
+            let reply = reply?;
+            if let Some(parent) = reply.references.last() {
+                replies.entry(parent.to_owned()).or_default().push(reply);
+            }
+        }
+        let annotations_tree = self.annotations()?;
+        for annotation in &mut annotations {
+            if let Some(parent) = annotation.annotation.references.last() {
+                if annotations_tree.contains_key(parent.as_bytes())? {
+                    annotation.parent = Some(self.get_annotation(parent)?);
+                } else {
+                    annotation.missing_parent = true;
+                }
+            }
+            if let Some(mut annotation_replies) = replies.remove(&annotation.annotation.id) {
+                annotation_replies.sort_by(|a, b| a.created.cmp(&b.created));
+                annotation.replies = annotation_replies;
+            }
+        }
+        if self.config.orphaned_replies == OrphanedReplies::Skip {
+            let num_annotations = annotations.len();
+            annotations.retain(|a| !a.missing_parent);
+            let num_skipped = num_annotations - annotations.len();
+            if num_skipped > 0 {
+                println!("Skipped {} reply(s) to missing annotations", num_skipped);
+            }
+        }
         let extension = self
             .config
             .file_extension
@@ -353,33 +1133,102 @@ impl Gooseberry {
                 .ok_or_else(|| eyre!("No index name"))?,
             extension
         ));
-        if index && index_file.exists() {
+        let human_index_file = self
+            .config
+            .human_index_name
+            .as_ref()
+            .map(|name| src_dir.join(format!("{}.{}", name, extension)));
+        if index && mode == BuildMode::Write {
             // Initialize
-            fs::remove_file(&index_file)?;
+            if index_file.exists() {
+                fs::remove_file(&index_file)?;
+            }
+            if let Some(human_index_file) = &human_index_file {
+                if human_index_file.exists() {
+                    fs::remove_file(human_index_file)?;
+                }
+            }
         }
 
         // Register templates
         let hbs = self.get_handlebars()?;
-        let pb = utils::get_spinner("Building knowledge base...")?;
+        let checker = BookChecker::new(mode);
+        if make && self.config.make_tag_graph {
+            checker.write(
+                &src_dir.join(format!("tags.{}", extension)),
+                &make_tag_graph(&annotations),
+            )?;
+        }
+        let render_annotations = |annotations: &[AnnotationTemplate]| {
+            let mut rendered = Vec::with_capacity(annotations.len());
+            for a in annotations {
+                if let Some(markdown) = checker.catch(
+                    hbs.render(&self.config.annotation_template_for(&a.annotation.tags), a),
+                    || format!("Annotation {}", a.annotation.id),
+                )? {
+                    rendered.push(markdown);
+                }
+            }
+            Ok::<_, RenderError>(rendered)
+        };
         sort_annotations(
             self.config.sort.as_ref().unwrap_or(&vec![OrderBy::Created]),
             &mut annotations,
         );
 
+        // Most recently updated annotations, for the index
+        let recent_annotations = match self.config.recent_count {
+            Some(recent_count) if index && recent_count > 0 => {
+                let mut recent_annotations = annotations.clone();
+                recent_annotations.sort_by(|a, b| b.annotation.updated.cmp(&a.annotation.updated));
+                recent_annotations.truncate(recent_count);
+                recent_annotations
+            }
+            _ => Vec::new(),
+        };
+
         let order = self
             .config
             .hierarchy
             .as_ref()
             .ok_or_else(|| eyre!("No hierarchy"))?;
+        let message = match mode {
+            BuildMode::Write => "Building knowledge base...",
+            BuildMode::Check | BuildMode::Drift => "Checking knowledge base...",
+        };
+        // Shows the number of pages made so far, or just a spinner for a single file
+        let pb = if order.is_empty() {
+            utils::get_spinner(message)?
+        } else {
+            let num_pages = count_pages(
+                order,
+                annotations.clone(),
+                self.config.nested_tag.as_ref(),
+                &self.config.uri_aliases,
+            );
+            utils::get_progress_bar(num_pages as u64, message)?
+        };
         if order.is_empty() {
             // Index file has all annotations
-            fs::File::create(&index_file)?.write_all(
-                annotations
-                    .into_iter()
-                    .map(|a| hbs.render("annotation", &a))
-                    .collect::<Result<String, _>>()?
-                    .as_bytes(),
-            )?;
+            let separator = self
+                .config
+                .annotation_separator
+                .as_deref()
+                .unwrap_or_default();
+            let index_contents = match self.config.section_headers {
+                None => render_annotations(&annotations)?.join(separator),
+                Some(section_header) => {
+                    let mut index_contents = String::new();
+                    for (name, section) in
+                        group_annotations_by_section(section_header, &annotations)
+                    {
+                        index_contents.push_str(&format!("\n## {}\n", name));
+                        index_contents.push_str(&render_annotations(&section)?.join(separator));
+                    }
+                    index_contents
+                }
+            };
+            checker.write(&index_file, &index_contents)?;
         } else {
             // Index file has links to each page
             let mut index_links = vec![];
@@ -389,54 +1238,105 @@ impl Gooseberry {
                     Vec<AnnotationTemplate>,
                     PathBuf,
                     usize,
-                    &mut Vec<String>,
+                    &str,
+                    &mut Vec<IndexLink>,
                 ) -> color_eyre::Result<()>,
             }
             let recurse_folder = RecurseFolder {
-                f: &|recurse_folder, inner_annotations, folder, depth, index_links| {
+                f: &|recurse_folder, inner_annotations, folder, depth, section, index_links| {
                     if depth == order.len() {
-                        let folder_name = folder.to_str().ok_or(Apologize::KBError {
+                        let relative_folder = folder.strip_prefix(src_dir)?;
+                        let folder_name = relative_folder.to_str().ok_or(Apologize::KBError {
                             message: format!("{:?} has non-unicode characters", folder),
                         })?;
-                        let folder_name: String = folder_name
-                            .chars()
-                            .take(250.min(folder_name.len()))
-                            .collect();
-                        let path = PathBuf::from(format!("{}.{}", folder_name, extension));
-                        let link_data = get_link_data(&path, src_dir)?;
+                        // Leave room for the extension
+                        let folder_name = checker.truncate(
+                            folder_name,
+                            self.config
+                                .max_filename_length
+                                .unwrap_or(DEFAULT_MAX_FILENAME_LENGTH)
+                                .saturating_sub(extension.chars().count() + 1)
+                                .max(1),
+                        );
+                        let path = src_dir.join(format!("{}.{}", folder_name, extension));
+                        checker.check_path(&path);
+                        let link_data = get_link_data(
+                            &path,
+                            src_dir,
+                            self.config.link_style == LinkStyle::Markdown,
+                        )?;
                         if index {
-                            index_links.push(hbs.render("index_link", &link_data)?);
+                            index_links.push(IndexLink {
+                                link_data: link_data.clone(),
+                                section: section.to_owned(),
+                                annotation_ids: inner_annotations
+                                    .iter()
+                                    .map(|a| a.annotation.id.to_owned())
+                                    .collect(),
+                            });
                         }
                         if make {
                             let page_data = PageTemplate {
                                 link_data,
-                                annotations: inner_annotations
-                                    .iter()
-                                    .map(|a| hbs.render("annotation", &a))
-                                    .collect::<Result<Vec<String>, _>>()?,
+                                annotations: render_annotations(&inner_annotations)?,
                                 raw_annotations: inner_annotations,
                             };
-                            // TODO: check if nested tags work on Windows
-                            if let Some(prefix) = path.parent() {
-                                fs::create_dir_all(prefix)?;
+                            let page = checker.catch(hbs.render("page", &page_data), || {
+                                format!("Page {:?}", path)
+                            })?;
+                            let frontmatter = if hbs.has_template("page_frontmatter") {
+                                checker.catch(hbs.render("page_frontmatter", &page_data), || {
+                                    format!("Frontmatter of page {:?}", path)
+                                })?
+                            } else {
+                                Some(String::new())
+                            };
+                            if let (Some(page), Some(frontmatter)) = (page, frontmatter) {
+                                let page = if frontmatter.trim().is_empty() {
+                                    page
+                                } else {
+                                    format!("---\n{}\n---\n{}", frontmatter.trim(), page)
+                                };
+                                // TODO: check if nested tags work on Windows
+                                if mode == BuildMode::Write {
+                                    if let Some(prefix) = path.parent() {
+                                        fs::create_dir_all(prefix)?;
+                                    }
+                                }
+                                checker.write(&path, &page)?;
                             }
-                            fs::File::create(&path)?
-                                .write_all(hbs.render("page", &page_data)?.as_bytes())?;
                         }
+                        pb.inc(1);
                     } else {
-                        if make && !folder.exists() {
+                        // Folders deeper than max_depth are collapsed into their children's names
+                        let collapse = self
+                            .config
+                            .max_depth
+                            .is_some_and(|max_depth| depth > max_depth);
+                        if make && mode == BuildMode::Write && !collapse && !folder.exists() {
                             fs::create_dir(&folder)?;
                         }
                         for (new_folder, annotations) in group_annotations_by_order(
                             order[depth],
                             inner_annotations,
                             self.config.nested_tag.as_ref(),
+                            &self.config.uri_aliases,
                         ) {
+                            // Pages are grouped in index sections by their first hierarchy level
+                            let section = if depth == 0 { &new_folder } else { section };
+                            let new_folder = if collapse {
+                                let folder_name =
+                                    folder.file_name().unwrap_or_default().to_string_lossy();
+                                folder.with_file_name(format!("{} - {}", folder_name, new_folder))
+                            } else {
+                                folder.join(&new_folder)
+                            };
                             (recurse_folder.f)(
                                 recurse_folder,
                                 annotations,
-                                folder.join(new_folder),
+                                new_folder,
                                 depth + 1,
+                                section,
                                 index_links,
                             )?;
                         }
@@ -450,27 +1350,169 @@ impl Gooseberry {
                 annotations,
                 PathBuf::from(src_dir),
                 0,
+                "",
                 &mut index_links,
             )?;
             if index {
+                // Recently updated section goes on the human-readable index if there is one
+                let mut recent_section = String::new();
+                if !recent_annotations.is_empty() {
+                    let recent: Vec<_> = recent_annotations
+                        .into_iter()
+                        .filter_map(|annotation| {
+                            index_links
+                                .iter()
+                                .find(|l| l.annotation_ids.contains(&annotation.annotation.id))
+                                .map(|l| RecentTemplate {
+                                    link_data: l.link_data.clone(),
+                                    annotation,
+                                })
+                        })
+                        .collect();
+                    recent_section = checker
+                        .catch(
+                            hbs.render("recent", &serde_json::json!({ "recent": recent })),
+                            || "Recently updated section".to_string(),
+                        )?
+                        .unwrap_or_default();
+                }
+                let has_sections = !order.is_empty() && hbs.has_template("index_section");
+                let render_links = |template_name: &str| {
+                    let mut links = String::new();
+                    let mut section = None;
+                    for l in &index_links {
+                        if has_sections && section != Some(&l.section) {
+                            section = Some(&l.section);
+                            if let Some(header) = checker.catch(
+                                hbs.render(
+                                    "index_section",
+                                    &serde_json::json!({ "name": l.section }),
+                                ),
+                                || format!("Index section {:?}", l.section),
+                            )? {
+                                links.push_str(&header);
+                            }
+                        }
+                        if let Some(link) = checker
+                            .catch(hbs.render(template_name, &l.link_data), || {
+                                format!("Index link to {:?}", l.link_data.relative_path)
+                            })?
+                        {
+                            links.push_str(&link);
+                        }
+                    }
+                    Ok::<_, RenderError>(links)
+                };
                 // Make Index file
-                fs::File::create(&index_file)?
-                    .write_all(index_links.into_iter().collect::<String>().as_bytes())?;
+                let mut index_contents = render_links("index_link")?;
+                if human_index_file.is_none() {
+                    index_contents = format!("{}{}", recent_section, index_contents);
+                }
+                checker.write(&index_file, &index_contents)?;
+                // Make human-readable Index file
+                if let Some(human_index_file) = &human_index_file {
+                    let human_index_contents =
+                        format!("{}{}", recent_section, render_links("human_index_link")?);
+                    checker.write(human_index_file, &human_index_contents)?;
+                }
             }
         }
         pb.finish_with_message("Done!");
-        if make {
-            println!(
-                "Knowledge base built at: {:?}",
-                self.config
-                    .kb_dir
-                    .as_ref()
-                    .ok_or_else(|| eyre!("No knowledge base directory"))?
+        if check {
+            let errors = checker.errors.into_inner();
+            if errors.is_empty() {
+                println!("No problems found");
+                return Ok(());
+            }
+            for error in &errors {
+                eprintln!("{}", error);
+            }
+            return Err(Apologize::KBError {
+                message: format!("Found {} problem(s) in the knowledge base", errors.len()),
+            }
+            .into());
+        }
+        if mode == BuildMode::Drift {
+            let stale = checker.stale.into_inner();
+            // Files gooseberry would no longer write, e.g. pages of removed tags.
+            // The index files are skipped since they're not rendered with --no-index
+            let rendered = checker.rendered.into_inner();
+            let mut extra = Vec::new();
+            if src_dir.exists() {
+                files_with_extension(src_dir, extension, &mut extra)?;
+            }
+            extra.retain(|path| {
+                !rendered.contains(path)
+                    && path != &index_file
+                    && human_index_file.as_ref() != Some(path)
+            });
+            extra.sort();
+            if stale.is_empty() && extra.is_empty() {
+                println!("Knowledge base is up to date");
+                return Ok(());
+            }
+            for path in &stale {
+                eprintln!("{}", path.display());
+            }
+            for path in &extra {
+                eprintln!("{} (no longer generated)", path.display());
+            }
+            let error: color_eyre::Result<()> = Err(Apologize::KBError {
+                message: format!(
+                    "{} file(s) out of date, {} stale file(s)",
+                    stale.len(),
+                    extra.len()
+                ),
+            }
+            .into());
+            return error.suggestion(
+                "Run `gooseberry make` to regenerate the knowledge base (with --clear to remove stale files)",
             );
         }
+        if make {
+            println!("Knowledge base built at: {:?}", src_dir);
+        }
         if index {
             println!("Index file location: {:?}", index_file);
+            if let Some(human_index_file) = &human_index_file {
+                if !order.is_empty() {
+                    println!("Human index file location: {:?}", human_index_file);
+                }
+            }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slugify() {
+        assert_eq!(slugify_text("Rust/Tips: 2"), "rust-tips-2");
+        // unicode letters are kept, lowercased
+        assert_eq!(slugify_text("Über Crème Brûlée"), "über-crème-brûlée");
+        assert_eq!(slugify_text("日本語のテキスト"), "日本語のテキスト");
+        // consecutive separators become one hyphen, none at the ends
+        assert_eq!(slugify_text("--a  --  b__c!!"), "a-b-c");
+        assert_eq!(
+            slugify_text("https://www.example.com/"),
+            "https-www-example-com"
+        );
+        assert_eq!(slugify_text("?!"), "");
+    }
+
+    #[test]
+    fn truncate_names() {
+        let checker = BookChecker::new(BuildMode::Write);
+        assert_eq!(checker.truncate("short", 10), "short");
+        assert_eq!(checker.truncate("long name one", 10), "long name ");
+        // cut to a name that was already used
+        assert_eq!(checker.truncate("long name two", 10), "long n (2)");
+        // fits, but is the same as an earlier name that was cut
+        assert_eq!(checker.truncate("long n (2)", 10), "long n (3)");
+        // the same name with different case, for case-insensitive file systems
+        assert_eq!(checker.truncate("Short", 10), "Short (2)");
+    }
+}
@@ -1,27 +1,172 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::Path;
 use std::{fs, vec};
 
+use chrono::{DateTime, Utc};
 use color_eyre::Help;
-use dialoguer::Confirm;
 use eyre::eyre;
-use hypothesis::annotations::{Annotation, Order, SearchQuery};
+use futures::future::{join_all, try_join_all};
+use futures::stream::{self, StreamExt};
+use handlebars::RenderError;
+use hypothesis::annotations::{Annotation, Document, InputAnnotation, Order, SearchQuery};
 use hypothesis::Hypothesis;
+use regex::RegexBuilder;
+use serde::Deserialize;
 
-use crate::configuration::GooseberryConfig;
+use crate::configuration::{
+    GooseberryConfig, SearchField, SectionHeader, DEFAULT_NESTED_TAG, DEFAULT_UPDATE_BATCH_SIZE,
+    DEFAULT_VIEW_HEADER_PREFIX,
+};
 use crate::errors::Apologize;
-use crate::gooseberry::cli::{ConfigCommand, Filters, GooseberryCLI, GooseberrySubcommand};
-use crate::gooseberry::knowledge_base::AnnotationTemplate;
+use crate::gooseberry::cli::{
+    ConfigCommand, DbCommand, FilterCommand, FilterPatterns, Filters, GooseberryCLI,
+    GooseberrySubcommand, GroupsCommand, Limit, Pattern,
+};
+use crate::gooseberry::database::Journal;
+use crate::gooseberry::export::ExportOptions;
+use crate::gooseberry::knowledge_base::{
+    group_annotations_by_section, truncate_text, AnnotationTemplate,
+};
 use crate::utils;
+use crate::EMPTY_TAG;
 
 /// Command-line interface with `structopt`
 pub mod cli;
 /// `sled` database related
 pub mod database;
+/// Export annotations in formats used by other tools
+pub mod export;
+/// Import annotations made elsewhere
+pub mod import;
 /// Convert annotations to text for the wiki and for the terminal
 pub mod knowledge_base;
 /// `skim`-based search capabilities
 pub mod search;
+/// `ratatui`-based terminal interface for browsing annotations
+pub mod tui;
+
+/// A tag in the nested tag tree, with the annotations tagged with it or any of its children
+#[derive(Default)]
+struct TagNode {
+    annotation_ids: HashSet<String>,
+    children: BTreeMap<String, TagNode>,
+}
+
+impl TagNode {
+    fn print(&self, depth: usize) {
+        for (name, node) in &self.children {
+            println!(
+                "{}{} ({})",
+                "    ".repeat(depth),
+                name,
+                node.annotation_ids.len()
+            );
+            node.print(depth + 1);
+        }
+    }
+}
+
+/// Prints markdown to the terminal with `bat`
+fn print_markdown(inputs: &[String]) -> color_eyre::Result<()> {
+    if inputs.is_empty() {
+        return Ok(());
+    }
+    bat::PrettyPrinter::new()
+        .language("markdown")
+        .inputs(inputs.iter().map(|i| bat::Input::from_bytes(i.as_bytes())))
+        .print()
+        .map_err(|_| eyre!("Bat printing error"))?;
+    Ok(())
+}
+
+/// Checks if the number of tags of an annotation is between `min` and `max` (inclusive).
+/// Empty tags and the `EMPTY_TAG` sentinel don't count towards the total.
+fn has_num_tags(annotation: &Annotation, min: Option<usize>, max: Option<usize>) -> bool {
+    let num_tags = annotation
+        .tags
+        .iter()
+        .filter(|tag| !tag.trim().is_empty() && *tag != EMPTY_TAG)
+        .count();
+    min.is_none_or(|min| num_tags >= min) && max.is_none_or(|max| num_tags <= max)
+}
+
+/// Puts annotations (sorted by creation date) newest first if `reverse` is set
+/// and keeps only the first `limit` of them
+fn limit_annotations(annotations: &mut Vec<Annotation>, limit: Limit) {
+    if limit.reverse {
+        annotations.reverse();
+    }
+    if let Some(limit) = limit.limit {
+        annotations.truncate(limit);
+    }
+}
+
+/// Hypothesis search API, queried directly by `sync --preview` for the number of matching annotations
+const HYPOTHESIS_SEARCH_URL: &str = "https://api.hypothes.is/api/search";
+
+/// A page of Hypothesis search results, along with the total number of matching annotations
+#[derive(Deserialize)]
+struct SearchPage {
+    total: usize,
+    rows: Vec<Annotation>,
+}
+
+/// First page of Hypothesis search results for `query`, retried like the other API calls
+async fn search_page(
+    client: &reqwest::Client,
+    key: &str,
+    query: &[(&str, &str)],
+    max_retries: usize,
+    pb: &indicatif::ProgressBar,
+) -> color_eyre::Result<SearchPage> {
+    utils::retry(max_retries, pb, || async move {
+        let response = client
+            .get(HYPOTHESIS_SEARCH_URL)
+            .bearer_auth(key)
+            .query(query)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok::<SearchPage, color_eyre::Report>(serde_json::from_str(&response.text().await?)?)
+    })
+    .await
+}
+
+/// Input to create `annotation` again in a group, keeping its URI, text, tags, document, and targets.
+/// Hypothesis creates annotations with a single target, so the selectors of all targets go into the first one
+fn to_input_annotation(
+    annotation: Annotation,
+    group_id: &str,
+) -> color_eyre::Result<InputAnnotation> {
+    let mut targets = annotation.target.into_iter();
+    let mut target = targets.next().unwrap_or_default();
+    for other in targets {
+        target.selector.extend(other.selector);
+    }
+    let mut input_annotation = InputAnnotation::builder()
+        .uri(annotation.uri)
+        .text(annotation.text)
+        .tags(annotation.tags)
+        .target(target)
+        .group(group_id)
+        .build()?;
+    input_annotation.document = annotation.document;
+    Ok(input_annotation)
+}
+
+/// Title of the web page at `uri`, if it can be fetched.
+/// Only the start of the page is read, up to `TITLE_FETCH_MAX_BYTES`
+async fn fetch_title(client: &reqwest::Client, uri: &str) -> Option<String> {
+    let mut response = client.get(uri).send().await.ok()?.error_for_status().ok()?;
+    let mut body = Vec::new();
+    while body.len() < utils::TITLE_FETCH_MAX_BYTES {
+        match response.chunk().await.ok()? {
+            Some(chunk) => body.extend_from_slice(&chunk),
+            None => break,
+        }
+    }
+    utils::html_title(&String::from_utf8_lossy(&body))
+}
 
 /// Gooseberry database, API client, and configuration
 pub struct Gooseberry {
@@ -44,16 +189,14 @@ impl Gooseberry {
                 .ok_or(Apologize::ConfigError {
                     message: "Hypothesis username isn't stored".into(),
                 })?,
-            config
-                .hypothesis_key
-                .as_deref()
-                .ok_or(Apologize::ConfigError {
-                    message: "Hypothesis developer API key isn't stored".into(),
-                })?,
+            &config.get_hypothesis_key()?.ok_or(Apologize::ConfigError {
+                message: "Hypothesis developer API key isn't stored".into(),
+            })?,
         )?;
         let db = Self::get_db(&config.db_dir)?;
         let gooseberry = Self { db, api, config };
         gooseberry.set_merge()?;
+        gooseberry.migrate_sync_time()?;
         Ok(gooseberry)
     }
 
@@ -76,6 +219,19 @@ impl Gooseberry {
             GooseberryCLI::complete(*shell);
             return Ok(());
         }
+        // Called by shell completions, so these don't check credentials or prompt for anything
+        if let GooseberrySubcommand::CompleteTags = &cli.cmd {
+            return Self::complete_tags(&GooseberryConfig::read(cli.config.as_deref())?);
+        }
+        if let GooseberrySubcommand::CompleteGroups = &cli.cmd {
+            let config = GooseberryConfig::read(cli.config.as_deref())?;
+            let mut names = config.hypothesis_groups.values().collect::<Vec<_>>();
+            names.sort();
+            for name in names {
+                println!("{}", name);
+            }
+            return Ok(());
+        }
         // Reads the GOOSEBERRY_CONFIG environment variable to get config file location
         let config = GooseberryConfig::load(cli.config.as_deref()).await?;
         let mut gooseberry = Gooseberry::new(config).await?;
@@ -86,7 +242,25 @@ impl Gooseberry {
     /// Run knowledge-base related functions
     pub async fn run(&mut self, cli: GooseberryCLI) -> color_eyre::Result<()> {
         match cli.cmd {
-            GooseberrySubcommand::Sync => self.sync().await,
+            GooseberrySubcommand::Sync { preview, strict } => {
+                if preview {
+                    self.sync_preview().await
+                } else {
+                    self.sync_groups(strict).await
+                }
+            }
+            GooseberrySubcommand::Refresh { id } => self.refresh(&id).await,
+            GooseberrySubcommand::Edit { id } => self.edit(&id).await,
+            GooseberrySubcommand::Add {
+                uri,
+                text,
+                tags,
+                group,
+                no_default_tags,
+            } => {
+                self.add(&uri, text, tags, group.as_deref(), no_default_tags)
+                    .await
+            }
             GooseberrySubcommand::Search { filters, fuzzy } => {
                 let annotations: Vec<Annotation> = self.filter_annotations(filters)?;
                 self.search(annotations, fuzzy).await
@@ -95,78 +269,294 @@ impl Gooseberry {
                 filters,
                 delete,
                 tag,
+                count,
             } => {
                 let annotations: Vec<Annotation> = self.filter_annotations(filters)?;
+                if count {
+                    println!("{}", annotations.len());
+                    return Ok(());
+                }
                 let tags = if tag.is_empty() { None } else { Some(tag) };
                 self.tag(annotations, delete, tags).await
             }
-            GooseberrySubcommand::Delete { filters, force } => {
+            GooseberrySubcommand::RenameTag { old, new, dry_run } => {
+                self.rename_tag(&old, &new, dry_run).await
+            }
+            GooseberrySubcommand::ApplyRules { filters } => {
+                let annotations: Vec<Annotation> = self.filter_annotations(filters)?;
+                self.apply_rules(annotations).await
+            }
+            GooseberrySubcommand::Delete {
+                filters,
+                force,
+                dry_run,
+                soft,
+                count,
+            } => {
                 let annotations = self.filter_annotations(filters)?;
-                self.delete(annotations, force).await
+                if count {
+                    println!("{}", annotations.len());
+                    Ok(())
+                } else if dry_run {
+                    Self::delete_preview(&annotations);
+                    Ok(())
+                } else if soft {
+                    self.soft_delete(annotations, force).await
+                } else {
+                    self.delete(annotations, force).await
+                }
+            }
+            GooseberrySubcommand::Undo => self.undo().await,
+            GooseberrySubcommand::View {
+                filters,
+                id,
+                tui,
+                threaded,
+                stats_footer,
+                group_by,
+                limit,
+                count,
+            } => {
+                if count {
+                    // Counts the annotations `view` would show
+                    let mut annotations = self.filter_annotations(filters)?;
+                    if self.config.skip_empty_annotations {
+                        utils::skip_empty_annotations(&mut annotations, |a| a);
+                    }
+                    limit_annotations(&mut annotations, limit);
+                    println!("{}", annotations.len());
+                    Ok(())
+                } else if tui {
+                    let mut annotations = self.filter_annotations(filters)?;
+                    limit_annotations(&mut annotations, limit);
+                    self.view_tui(annotations).await
+                } else {
+                    self.view(filters, id, threaded, stats_footer, group_by, limit)
+                }
             }
-            GooseberrySubcommand::View { filters, id } => self.view(filters, id),
             GooseberrySubcommand::Move {
                 group_id,
                 filters,
+                to,
                 search,
                 fuzzy,
-            } => self.sync_group(group_id, filters, search, fuzzy).await,
+                no_default_tags,
+                copy,
+            } => {
+                if copy {
+                    let to_group_id = self.get_move_target(to.as_deref())?;
+                    let annotations = self
+                        .annotations_to_move(group_id, filters, search, fuzzy)
+                        .await?;
+                    self.copy_annotations(annotations, &to_group_id, no_default_tags)
+                        .await
+                } else {
+                    self.sync_group(group_id, filters, to, search, fuzzy, no_default_tags)
+                        .await
+                }
+            }
             GooseberrySubcommand::Make {
                 filters,
                 clear,
                 force,
                 no_index,
-            } => self.make(
-                self.filter_annotations_make(filters)?,
-                clear,
+                check,
+                check_drift,
+                output_dir,
+                profile,
+                sort,
+                open,
+                count,
+            } => {
+                if let Some(profile) = &profile {
+                    self.config.use_profile(profile)?;
+                }
+                if let Some(sort) = sort {
+                    self.config.sort = Some(sort);
+                }
+                let mut annotations = self.filter_annotations_make(filters)?;
+                if count {
+                    println!("{}", annotations.len());
+                    return Ok(());
+                }
+                self.fill_titles(&mut annotations).await?;
+                if check {
+                    self.check_make(annotations, !no_index)
+                } else if check_drift {
+                    self.check_drift(annotations, !no_index)
+                } else {
+                    self.make(
+                        annotations,
+                        clear,
+                        force,
+                        true,
+                        !no_index,
+                        output_dir.as_deref(),
+                    )?;
+                    if open {
+                        self.open_kb(output_dir.as_deref())?;
+                    }
+                    Ok(())
+                }
+            }
+            GooseberrySubcommand::Index {
+                filters,
+                output_dir,
+                profile,
+            } => {
+                if let Some(profile) = &profile {
+                    self.config.use_profile(profile)?;
+                }
+                let mut annotations = self.filter_annotations_make(filters)?;
+                self.fill_titles(&mut annotations).await?;
+                self.make(
+                    annotations,
+                    false,
+                    false,
+                    false,
+                    true,
+                    output_dir.as_deref(),
+                )
+            }
+            GooseberrySubcommand::Import {
+                file,
+                url,
+                to,
+                no_default_tags,
+                local,
+            } => {
+                self.import(
+                    file.as_deref(),
+                    url.as_deref(),
+                    to.as_deref(),
+                    no_default_tags,
+                    local,
+                )
+                .await
+            }
+            GooseberrySubcommand::Export {
+                filters,
+                format,
+                split,
+                output,
                 force,
-                true,
-                !no_index,
-            ),
-            GooseberrySubcommand::Index { filters } => self.make(
-                self.filter_annotations_make(filters)?,
-                false,
-                false,
-                false,
-                true,
-            ),
+                deck_name,
+                limit,
+                sort,
+            } => {
+                let mut annotations = self.filter_annotations(filters)?;
+                limit_annotations(&mut annotations, limit);
+                self.export(
+                    annotations,
+                    ExportOptions {
+                        format,
+                        split,
+                        output: output.as_deref(),
+                        force,
+                        deck_name: deck_name.as_deref(),
+                        sort: sort.as_deref(),
+                    },
+                )
+            }
+            GooseberrySubcommand::Filter { cmd } => match cmd {
+                FilterCommand::Save { name, filters } => {
+                    let filters = self.resolve_saved_filters(filters)?;
+                    self.save_filters(&name, &filters)?;
+                    println!("Saved filters as {:?}", name);
+                    Ok(())
+                }
+                FilterCommand::List => {
+                    for name in self.get_saved_filter_names()? {
+                        println!("{}", name);
+                    }
+                    Ok(())
+                }
+                FilterCommand::Delete { name } => self.delete_saved_filters(&name),
+            },
+            GooseberrySubcommand::Tags { tree } => self.tags(tree),
+            GooseberrySubcommand::Db { cmd } => match cmd {
+                DbCommand::Compact { rewrite } => self.compact(rewrite),
+            },
+            GooseberrySubcommand::Groups { cmd } => match cmd {
+                GroupsCommand::Missing { from } => self.missing_groups(from).await,
+            },
             GooseberrySubcommand::Clear { force } => self.clear(force),
             GooseberrySubcommand::Uri { filters, ids } => {
                 let annotations: Vec<Annotation> = self.filter_annotations(filters)?;
                 self.uri(annotations, ids)
             }
+            GooseberrySubcommand::Find {
+                query,
+                markdown,
+                json,
+            } => self.find(&query, markdown, json),
+            GooseberrySubcommand::Uris { filters } => {
+                let annotations: Vec<Annotation> = self.filter_annotations(filters)?;
+                Self::uris(annotations);
+                Ok(())
+            }
             _ => Ok(()), // Already handled
         }
     }
 
-    /// Sync newly added / updated annotations
+    /// Sync newly added / updated annotations, reporting groups that fail without stopping the others
     pub async fn sync(&self) -> color_eyre::Result<()> {
+        self.sync_groups(false).await
+    }
+
+    /// Sync newly added / updated annotations group by group.
+    /// With `strict`, stops at the first group that can't be fetched from Hypothesis,
+    /// otherwise the other groups are still synced and the failed ones are reported at the end, as an error.
+    /// Database errors always stop the sync
+    pub async fn sync_groups(&self, strict: bool) -> color_eyre::Result<()> {
         let spinner = utils::get_spinner("Syncing...")?;
         // Sleep to make sure the previous requests are processed
         let duration = core::time::Duration::from_millis(500);
         std::thread::sleep(duration);
 
-        let groups = self
+        let mut groups = self
             .config
             .hypothesis_groups
             .keys()
             .cloned()
             .collect::<Vec<_>>();
+        groups.sort();
 
         if groups.is_empty() {
             spinner.finish_with_message("No groups to sync!");
             return Ok(());
         }
-        let mut query = SearchQuery::builder()
-            .limit(200)
-            .order(Order::Asc)
-            .search_after(self.get_sync_time()?)
-            .user(&self.api.user.0)
-            .group(groups)
-            .build()?;
-        let (added, updated) =
-            self.sync_annotations(self.api.search_annotations_return_all(&mut query).await?)?;
-        self.set_sync_time(&query.search_after)?;
+        let (mut added, mut updated) = (0, 0);
+        let mut failed = Vec::new();
+        // Fetch each group's annotations concurrently, then add them to the database group by group
+        let spinner = &spinner;
+        let queries = groups
+            .iter()
+            .map(|group| self.sync_query(group))
+            .collect::<color_eyre::Result<Vec<_>>>()?;
+        let fetched = join_all(queries.into_iter().map(|mut query| async move {
+            let annotations = self.search_annotations(&mut query, spinner).await?;
+            Ok::<_, color_eyre::Report>((annotations, query.search_after))
+        }))
+        .await;
+        for (group, fetched) in groups.into_iter().zip(fetched) {
+            match fetched {
+                Ok((annotations, sync_time)) => {
+                    let (group_added, group_updated) = self.sync_annotations(annotations)?;
+                    self.set_sync_time(&[group.to_owned()], &sync_time)?;
+                    added += group_added;
+                    updated += group_updated;
+                }
+                Err(error) if strict => {
+                    spinner.finish_and_clear();
+                    return Err(error).suggestion(format!(
+                        "Syncing group {:?} failed, leave out --strict to sync the other groups anyway",
+                        self.config.hypothesis_groups[&group]
+                    ));
+                }
+                Err(error) => failed.push((group, error)),
+            }
+        }
         spinner.finish_with_message("Done!");
         if added > 0 {
             if added == 1 {
@@ -182,9 +572,254 @@ impl Gooseberry {
                 println!("Updated {} annotations", updated);
             }
         }
-        if added == 0 && updated == 0 {
+        if added == 0 && updated == 0 && failed.is_empty() {
             println!("Everything up to date!")
         }
+        if failed.is_empty() {
+            return Ok(());
+        }
+        for (group, error) in &failed {
+            eprintln!(
+                "Couldn't sync group {:?} ({}): {}",
+                self.config.hypothesis_groups[group], group, error
+            );
+        }
+        let error: color_eyre::Result<()> = Err(Apologize::SyncError {
+            num_groups: failed.len(),
+        }
+        .into());
+        error.suggestion("The other groups were synced, check the failed group IDs with 'gooseberry config group'")
+    }
+
+    /// Query for a group's annotations added or updated since its last sync.
+    /// Groups that were never synced are back-filled
+    fn sync_query(&self, group: &str) -> color_eyre::Result<SearchQuery> {
+        Ok(SearchQuery::builder()
+            .limit(self.config.sync_limit())
+            .order(Order::Asc)
+            .search_after(self.get_sync_time(group)?)
+            .user(&self.api.user.0)
+            .group(vec![group.to_owned()])
+            .build()?)
+    }
+
+    /// Fetch one annotation from Hypothesis and update it in the database
+    pub async fn refresh(&self, id: &str) -> color_eyre::Result<()> {
+        let annotation = self
+            .api
+            .fetch_annotation(id)
+            .await
+            .suggestion("Are you sure this is a valid and existing annotation ID?")?;
+        if !self
+            .config
+            .hypothesis_groups
+            .contains_key(&annotation.group)
+        {
+            println!(
+                "Annotation {} is in group {}, which gooseberry doesn't sync",
+                id, annotation.group
+            );
+            return Ok(());
+        }
+        let updated = annotation.updated;
+        match self.refresh_annotation(annotation)? {
+            None => println!("Added annotation {}", id),
+            Some(previous) if previous.updated < updated => println!(
+                "Updated annotation {} (last synced version from {})",
+                id,
+                previous.updated.format("%F %T")
+            ),
+            Some(_) => println!("Annotation {} is already up to date", id),
+        }
+        Ok(())
+    }
+
+    /// Edit the text of an annotation in the external editor and update it on Hypothesis
+    pub async fn edit(&self, id: &str) -> color_eyre::Result<()> {
+        let mut annotation = self
+            .get_annotation(id)
+            .suggestion("Try running `gooseberry sync` or `gooseberry refresh` first")?;
+        let text = utils::external_editor_input(Some(&annotation.text), ".md")?;
+        if text == annotation.text {
+            println!("Annotation {} is unchanged", id);
+            return Ok(());
+        }
+        annotation.text = text;
+        self.update_annotations(&[annotation]).await?;
+        println!("Updated annotation {}", id);
+        self.sync().await?;
+        Ok(())
+    }
+
+    /// Create an annotation on Hypothesis, writing its text in the external editor if not given
+    pub async fn add(
+        &self,
+        uri: &str,
+        text: Option<String>,
+        tags: Vec<String>,
+        group: Option<&str>,
+        no_default_tags: bool,
+    ) -> color_eyre::Result<()> {
+        let group_id = match group {
+            Some(group) => self.get_move_target(Some(group))?,
+            None => self
+                .config
+                .hypothesis_groups
+                .iter()
+                .min_by(|(_, a), (_, b)| a.cmp(b))
+                .map(|(id, _)| id.to_owned())
+                .ok_or_else(|| Apologize::ConfigError {
+                    message: "Gooseberry doesn't have any groups".into(),
+                })
+                .suggestion("Set a group with `gooseberry config group`")?,
+        };
+        let text = match text {
+            Some(text) => text,
+            None => utils::external_editor_input(None, ".md")?,
+        };
+        if text.trim().is_empty() && tags.is_empty() {
+            println!("Nothing to add");
+            return Ok(());
+        }
+        let mut tags = tags
+            .into_iter()
+            .filter(|t| !t.trim().is_empty())
+            .collect::<Vec<_>>();
+        if !no_default_tags {
+            self.add_default_tags(&mut tags);
+        }
+        let input_annotation = InputAnnotation::builder()
+            .uri(uri)
+            .text(text)
+            .tags(tags)
+            .group(&group_id)
+            .build()?;
+        let annotation = self.api.create_annotation(&input_annotation).await?;
+        println!("Added annotation {}", annotation.id);
+        self.sync().await?;
+        Ok(())
+    }
+
+    /// Report groups with annotations updated after `from` that aren't in `hypothesis_groups`
+    pub async fn missing_groups(&self, from: Option<DateTime<Utc>>) -> color_eyre::Result<()> {
+        let spinner = utils::get_spinner("Checking groups...")?;
+        let from = from.unwrap_or_else(|| Utc::now() - chrono::Duration::days(30));
+        let mut query = SearchQuery::builder()
+            .limit(self.config.sync_limit())
+            .order(Order::Asc)
+            .search_after(from.to_rfc3339())
+            .user(&self.api.user.0)
+            .build()?;
+        let mut missing: BTreeMap<String, usize> = BTreeMap::new();
+        for annotation in self.search_annotations(&mut query, &spinner).await? {
+            if !self
+                .config
+                .hypothesis_groups
+                .contains_key(&annotation.group)
+            {
+                *missing.entry(annotation.group).or_default() += 1;
+            }
+        }
+        spinner.finish_and_clear();
+        if missing.is_empty() {
+            println!("All your recent annotations are in gooseberry's groups");
+            return Ok(());
+        }
+        let group_names: HashMap<String, String> = self
+            .api
+            .get_groups(&hypothesis::groups::GroupFilters::default())
+            .await?
+            .into_iter()
+            .map(|group| (group.id, group.name))
+            .collect();
+        println!("Groups with annotations that aren't synced:");
+        for (group_id, count) in &missing {
+            println!(
+                "{}: {} ({} annotations)",
+                group_id,
+                group_names
+                    .get(group_id)
+                    .map_or("unknown group", String::as_str),
+                count
+            );
+        }
+        println!(
+            "Add them with `gooseberry config group {}`",
+            missing.keys().cloned().collect::<Vec<_>>().join(",")
+        );
+        Ok(())
+    }
+
+    /// Report what `sync` would fetch per group without changing the database.
+    /// Only asks Hypothesis for the number of annotations and for the first and last one
+    pub async fn sync_preview(&self) -> color_eyre::Result<()> {
+        let spinner = utils::get_spinner("Checking...")?;
+        let mut groups = self
+            .config
+            .hypothesis_groups
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        if groups.is_empty() {
+            spinner.finish_with_message("No groups to sync!");
+            return Ok(());
+        }
+        groups.sort();
+        let key = self
+            .config
+            .get_hypothesis_key()?
+            .ok_or(Apologize::ConfigError {
+                message: "Hypothesis developer API key isn't stored".into(),
+            })?;
+        let client = reqwest::Client::builder()
+            .timeout(utils::API_TIMEOUT)
+            .build()?;
+        let (spinner, client, key) = (&spinner, &client, key.as_str());
+        let user = self.api.user.0.as_str();
+        // Query each group concurrently, results stay in the order of `groups`
+        let group_previews = try_join_all(groups.into_iter().map(|group| async move {
+            let sync_time = self.get_sync_time(&group)?;
+            let max_retries = self.config.max_retries();
+            let base_query = vec![
+                ("limit", "1"),
+                ("sort", "updated"),
+                ("user", user),
+                ("group", group.as_str()),
+            ];
+            let mut first_query = base_query.clone();
+            first_query.extend([("order", "asc"), ("search_after", sync_time.as_str())]);
+            let first = search_page(client, key, &first_query, max_retries, spinner).await?;
+            // The most recently updated annotation is one of the new ones, if there are any
+            let last = if first.total > 0 {
+                let mut last_query = base_query;
+                last_query.push(("order", "desc"));
+                search_page(client, key, &last_query, max_retries, spinner)
+                    .await?
+                    .rows
+                    .into_iter()
+                    .next()
+            } else {
+                None
+            };
+            let first_annotation = first.rows.into_iter().next();
+            Ok::<_, color_eyre::Report>((group, sync_time, first.total, first_annotation, last))
+        }))
+        .await?;
+        spinner.finish_with_message("Done!");
+        println!("Changes since last sync:");
+        for (group, sync_time, total, first, last) in group_previews {
+            println!(
+                "{} ({}): {} to add or update since {}",
+                self.config.hypothesis_groups[&group], group, total, sync_time
+            );
+            if let (Some(first), Some(last)) = (first, last) {
+                println!(
+                    "    updated between {} and {}",
+                    first.updated.format("%F %T"),
+                    last.updated.format("%F %T")
+                );
+            }
+        }
         Ok(())
     }
 
@@ -193,59 +828,139 @@ impl Gooseberry {
         &mut self,
         group_id: String,
         filters: Filters,
+        to: Option<String>,
         search: bool,
         fuzzy: bool,
+        no_default_tags: bool,
     ) -> color_eyre::Result<()> {
-        let mut annotations = self
-            .filter_annotations_api(filters, vec![group_id.clone()])
+        let to_group_id = self.get_move_target(to.as_deref())?;
+        let annotations = self
+            .annotations_to_move(group_id, filters, search, fuzzy)
             .await?;
+        let num = annotations.len();
+        // Change the group ID attached to each annotation
+        self.update_annotations(
+            &annotations
+                .into_iter()
+                .map(|mut a| {
+                    a.group = to_group_id.to_owned();
+                    if !no_default_tags {
+                        self.add_default_tags(&mut a.tags);
+                    }
+                    a
+                })
+                .collect::<Vec<_>>(),
+        )
+        .await?;
+        if num > 0 {
+            self.sync().await?;
+        }
+        Ok(())
+    }
+
+    /// Fetch (optionally filtered) annotations from a different group,
+    /// narrowed down in a search window if `search` or `fuzzy` is set
+    async fn annotations_to_move(
+        &self,
+        group_id: String,
+        filters: Filters,
+        search: bool,
+        fuzzy: bool,
+    ) -> color_eyre::Result<Vec<Annotation>> {
+        let mut annotations = self.filter_annotations_api(filters, vec![group_id]).await?;
         if search || fuzzy {
             // Run a search window.
             let annotation_ids = self.search_group(&annotations, fuzzy)?;
             annotations.retain(|a| annotation_ids.contains(&a.id))
         }
-        let num = annotations.len();
-        // Change the group ID attached to each annotation
-        self.api
-            .update_annotations(
-                &annotations
-                    .into_iter()
-                    .map(|mut a| {
-                        a.group = group_id.to_owned();
-                        a
-                    })
-                    .collect::<Vec<_>>(),
-            )
-            .await?;
-        if num > 0 {
-            self.sync().await?;
+        Ok(annotations)
+    }
+
+    /// Create copies of annotations in another group, since Hypothesis can't copy annotations.
+    /// The copies get new IDs and creation times
+    async fn copy_annotations(
+        &self,
+        annotations: Vec<Annotation>,
+        to_group_id: &str,
+        no_default_tags: bool,
+    ) -> color_eyre::Result<()> {
+        if annotations.is_empty() {
+            println!("No annotations to copy");
+            return Ok(());
         }
+        let input_annotations = annotations
+            .into_iter()
+            .map(|mut annotation| {
+                if !no_default_tags {
+                    self.add_default_tags(&mut annotation.tags);
+                }
+                to_input_annotation(annotation, to_group_id)
+            })
+            .collect::<color_eyre::Result<Vec<_>>>()?;
+        self.api.create_annotations(&input_annotations).await?;
+        println!(
+            "Copied {} annotation(s), note that the copies have new IDs",
+            input_annotations.len()
+        );
+        self.sync().await?;
         Ok(())
     }
 
+    /// Adds the `default_tags` from the config that aren't in `tags` yet
+    fn add_default_tags(&self, tags: &mut Vec<String>) {
+        for tag in &self.config.default_tags {
+            if !tags.contains(tag) {
+                tags.push(tag.to_owned());
+            }
+        }
+    }
+
+    /// Finds the gooseberry group to move annotations to, by ID or name.
+    /// Defaults to the only group if gooseberry uses just one.
+    fn get_move_target(&self, to: Option<&str>) -> color_eyre::Result<String> {
+        let groups = &self.config.hypothesis_groups;
+        match to {
+            Some(to) => groups
+                .iter()
+                .find(|(id, name)| *id == to || *name == to)
+                .map(|(id, _)| id.to_owned())
+                .ok_or_else(|| {
+                    Apologize::ConfigError {
+                        message: format!("{:?} isn't one of gooseberry's groups", to),
+                    }
+                    .into()
+                }),
+            None if groups.len() == 1 => Ok(groups.keys().next().unwrap().to_owned()),
+            None => Err(Apologize::ConfigError {
+                message: "Gooseberry uses more than one group".into(),
+            })
+            .suggestion("Choose the group to move to with --to <group>"),
+        }
+    }
+
     /// Filter annotations using hypothesis API based on command-line flags
     pub async fn filter_annotations_api(
         &self,
         filters: Filters,
         groups: Vec<String>,
     ) -> color_eyre::Result<Vec<Annotation>> {
+        let filters = self.resolve_saved_filters(filters)?;
+        let patterns = filters.patterns()?;
         let mut query: SearchQuery = filters.clone().into();
+        query.limit = self.config.sync_limit();
         query.user = self.api.user.0.to_owned();
         query.group = groups.clone();
+        let pb = indicatif::ProgressBar::hidden();
         let mut annotations = if !filters.and && !filters.tags.is_empty() {
             let mut annotations = Vec::new();
             for tag in &filters.tags {
                 let mut tag_query = query.clone();
                 tag_query.tags = vec![tag.to_string()];
-                annotations.extend(
-                    self.api
-                        .search_annotations_return_all(&mut tag_query)
-                        .await?,
-                );
+                annotations.extend(self.search_annotations(&mut tag_query, &pb).await?);
             }
             annotations
         } else {
-            self.api.search_annotations_return_all(&mut query).await?
+            self.search_annotations(&mut query, &pb).await?
         };
         if !filters.exclude_tags.is_empty() {
             annotations.retain(|a| !a.tags.iter().any(|t| filters.exclude_tags.contains(t)));
@@ -256,31 +971,170 @@ impl Gooseberry {
         if filters.annotation {
             annotations.retain(|a| a.target.iter().any(|t| !t.selector.is_empty()));
         }
+        if filters.has_text {
+            annotations.retain(|a| !a.text.trim().is_empty());
+        }
+        if filters.no_text {
+            annotations.retain(|a| a.text.trim().is_empty());
+        }
+        if filters.min_tags.is_some() || filters.max_tags.is_some() {
+            annotations.retain(|a| has_num_tags(a, filters.min_tags, filters.max_tags));
+        }
+        if !filters.authors.is_empty() {
+            annotations.retain(|a| {
+                filters
+                    .authors
+                    .iter()
+                    .any(|author| utils::is_user(&a.user.0, author))
+            });
+        }
+        if filters.media {
+            annotations.retain(|a| self.config.is_media(&a.uri));
+        }
+        if filters.stale_review {
+            let mut stale = Vec::with_capacity(annotations.len());
+            for annotation in annotations {
+                if self.is_stale_review(&annotation)? {
+                    stale.push(annotation);
+                }
+            }
+            annotations = stale;
+        }
+        if let (Some(_), Some(before)) = (filters.from, filters.before) {
+            annotations.retain(|a| {
+                if filters.include_updated {
+                    a.updated <= before
+                } else {
+                    a.created <= before
+                }
+            });
+        }
+        // The search is limited to `groups`, so --groups (which can also be group names) is checked afterwards
+        if !filters.groups.is_empty() {
+            annotations.retain(|a| self.in_groups(a, &filters.groups));
+        }
+        if filters.regex {
+            annotations.retain(|a| {
+                patterns.uri.matches(&a.uri)
+                    && patterns.text.matches(&a.text)
+                    && patterns.quote.matches(&utils::get_quotes(a).join(" "))
+                    && (patterns.any.is_empty() || self.matches_any(a, &patterns.any))
+            });
+        }
         if filters.not {
             let mut query: SearchQuery = Filters::default().into();
+            query.limit = self.config.sync_limit();
             query.user = self.api.user.0.to_owned();
             query.group = groups;
-            let mut all_annotations: Vec<_> =
-                self.api.search_annotations_return_all(&mut query).await?;
+            let mut all_annotations: Vec<_> = self.search_annotations(&mut query, &pb).await?;
             let remove_ids = annotations.iter().map(|a| &a.id).collect::<HashSet<_>>();
             all_annotations.retain(|a| !remove_ids.contains(&a.id));
             annotations = all_annotations;
         }
+        if !filters.exclude_ids.is_empty() {
+            annotations.retain(|a| !filters.exclude_ids.contains(&a.id));
+        }
         annotations.sort_by(|a, b| a.created.cmp(&b.created));
         Ok(annotations)
     }
 
-    pub fn filter_annotation(&self, annotation: &Annotation, filters: &Filters) -> bool {
+    /// Checks if an annotation is in one of the given groups, given by ID or by name in `hypothesis_groups`.
+    /// Any group matches if none are given.
+    fn in_groups(&self, annotation: &Annotation, groups: &[String]) -> bool {
+        groups.is_empty()
+            || groups.contains(&annotation.group)
+            || self
+                .config
+                .hypothesis_groups
+                .get(&annotation.group)
+                .is_some_and(|name| groups.contains(name))
+    }
+
+    /// Checks if an annotation was updated after it was last reviewed.
+    /// Annotations that were never reviewed aren't stale.
+    fn is_stale_review(&self, annotation: &Annotation) -> color_eyre::Result<bool> {
+        Ok(self
+            .get_review_time(&annotation.id)?
+            .is_some_and(|review_time| annotation.updated > review_time))
+    }
+
+    /// Replaces filters given with `--saved <NAME>` by the filters saved under that name
+    pub fn resolve_saved_filters(&self, filters: Filters) -> color_eyre::Result<Filters> {
+        match &filters.saved {
+            Some(name) => self.get_saved_filters(name),
+            None => Ok(filters),
+        }
+    }
+
+    /// Checks if the pattern is in the quote, tags, text, or URI of an annotation
+    /// (or the fields set in `any_search_fields`)
+    fn matches_any(&self, annotation: &Annotation, pattern: &Pattern) -> bool {
+        self.config
+            .any_search_fields()
+            .iter()
+            .any(|field| match field {
+                SearchField::Quote => pattern.matches(&utils::get_quotes(annotation).join(" ")),
+                SearchField::Tags => annotation.tags.iter().any(|t| pattern.matches(t)),
+                SearchField::Text => pattern.matches(&annotation.text),
+                SearchField::Uri => pattern.matches(&annotation.uri),
+            })
+    }
+
+    /// Search the local database for annotations containing all words of the query (ignoring case)
+    /// in their quote, tags, text, or URI, and print their IDs, rendered markdown, or JSON
+    pub fn find(&self, query: &str, markdown: bool, json: bool) -> color_eyre::Result<()> {
+        let patterns = query
+            .split_whitespace()
+            .map(|word| {
+                RegexBuilder::new(&regex::escape(word))
+                    .case_insensitive(true)
+                    .build()
+                    .map(Pattern::Regex)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut annotations = Vec::new();
+        for annotation in self.iter_annotations()? {
+            let annotation = annotation?;
+            if patterns
+                .iter()
+                .all(|pattern| self.matches_any(&annotation, pattern))
+            {
+                annotations.push(annotation);
+            }
+        }
+        annotations.sort_by(|a, b| a.created.cmp(&b.created));
+        if json {
+            println!("{}", serde_json::to_string_pretty(&annotations)?);
+        } else if markdown {
+            let hbs = self.get_handlebars()?;
+            for annotation in annotations {
+                println!(
+                    "{}",
+                    hbs.render(
+                        &self.config.annotation_template_for(&annotation.tags),
+                        &AnnotationTemplate::from_annotation(
+                            annotation,
+                            &self.config.hypothesis_groups
+                        ),
+                    )?
+                );
+            }
+        } else {
+            for annotation in annotations {
+                println!("{}", annotation.id);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn filter_annotation(
+        &self,
+        annotation: &Annotation,
+        filters: &Filters,
+        patterns: &FilterPatterns,
+    ) -> bool {
         // Check if in groups
-        if !filters.groups.is_empty()
-            && !filters.groups.contains(&annotation.group)
-            && !filters.groups.contains(
-                self.config
-                    .hypothesis_groups
-                    .get(&annotation.group)
-                    .unwrap_or(&annotation.group),
-            )
-        {
+        if !self.in_groups(annotation, &filters.groups) {
             return false;
         }
 
@@ -292,6 +1146,18 @@ impl Gooseberry {
         if filters.annotation && annotation.target.iter().all(|t| t.selector.is_empty()) {
             return false;
         }
+        // Check if there's a comment
+        if filters.has_text && annotation.text.trim().is_empty() {
+            return false;
+        }
+        // Check if there's no comment
+        if filters.no_text && !annotation.text.trim().is_empty() {
+            return false;
+        }
+        // Check number of tags
+        if !has_num_tags(annotation, filters.min_tags, filters.max_tags) {
+            return false;
+        }
         // Check if date > from date
         if let Some(from) = filters.from {
             if filters.include_updated {
@@ -313,19 +1179,12 @@ impl Gooseberry {
             }
         }
         // Check if URI has pattern
-        if !filters.uri.is_empty() && !annotation.uri.contains(&filters.uri) {
+        if !patterns.uri.matches(&annotation.uri) {
             return false;
         }
 
-        // Check if pattern in quote, tags, text, or URI
-        if !(filters.any.is_empty()
-            || utils::get_quotes(annotation)
-                .join(" ")
-                .contains(&filters.any)
-            || annotation.tags.iter().any(|t| t.contains(&filters.any))
-            || annotation.text.contains(&filters.any)
-            || annotation.uri.contains(&filters.any))
-        {
+        // Check if pattern in quote, tags, text, or URI (or the fields set in `any_search_fields`)
+        if !(patterns.any.is_empty() || self.matches_any(annotation, &patterns.any)) {
             return false;
         }
 
@@ -353,16 +1212,31 @@ impl Gooseberry {
         }
 
         // Check if pattern in quote
-        if !filters.quote.is_empty()
-            && !utils::get_quotes(annotation)
-                .join(" ")
-                .contains(&filters.quote)
+        if !patterns.quote.is_empty()
+            && !patterns
+                .quote
+                .matches(&utils::get_quotes(annotation).join(" "))
         {
             return false;
         }
 
         // Check if pattern in text
-        if !filters.text.is_empty() && !annotation.text.contains(&filters.text) {
+        if !patterns.text.matches(&annotation.text) {
+            return false;
+        }
+
+        // Check if created by one of the authors
+        if !filters.authors.is_empty()
+            && !filters
+                .authors
+                .iter()
+                .any(|author| utils::is_user(&annotation.user.0, author))
+        {
+            return false;
+        }
+
+        // Check if on a media-hosting site
+        if filters.media && !self.config.is_media(&annotation.uri) {
             return false;
         }
         true
@@ -370,10 +1244,16 @@ impl Gooseberry {
 
     /// Filter annotations based on command-line flags
     pub fn filter_annotations(&self, filters: Filters) -> color_eyre::Result<Vec<Annotation>> {
+        let filters = self.resolve_saved_filters(filters)?;
+        let patterns = filters.patterns()?;
         let mut annotations = Vec::new();
         for annotation in self.iter_annotations()? {
             let annotation = annotation?;
-            let keep = self.filter_annotation(&annotation, &filters);
+            let mut keep = self.filter_annotation(&annotation, &filters, &patterns);
+            // Checked here instead of in filter_annotation since reading review times can fail
+            if keep && filters.stale_review {
+                keep = self.is_stale_review(&annotation)?;
+            }
             if filters.not {
                 // If NOT, keep everything that doesn't match
                 if !keep {
@@ -383,6 +1263,9 @@ impl Gooseberry {
                 annotations.push(annotation);
             }
         }
+        if !filters.exclude_ids.is_empty() {
+            annotations.retain(|a| !filters.exclude_ids.contains(&a.id));
+        }
         annotations.sort_by(|a, b| a.created.cmp(&b.created));
         Ok(annotations)
     }
@@ -400,7 +1283,7 @@ impl Gooseberry {
                     self.config
                         .ignore_tags
                         .as_ref()
-                        .map(|ignore_tags| ignore_tags.contains(t))
+                        .map(|ignore_tags| utils::is_ignored_tag(t, ignore_tags))
                         .unwrap_or(false)
                 })
             })
@@ -409,6 +1292,131 @@ impl Gooseberry {
         Ok(annotations)
     }
 
+    /// With `fetch_titles`, gives annotations without a document title the title of the web page,
+    /// fetched once and cached in the database, or else their URI
+    async fn fill_titles(&self, annotations: &mut [Annotation]) -> color_eyre::Result<()> {
+        if !self.config.fetch_titles {
+            return Ok(());
+        }
+        let has_no_title = |annotation: &Annotation| {
+            utils::document_title(annotation).is_none_or(|title| title.trim().is_empty())
+        };
+        let mut uncached = Vec::new();
+        for annotation in annotations.iter().filter(|a| has_no_title(a)) {
+            let uri = &annotation.uri;
+            if uri.starts_with("http")
+                && !uncached.contains(uri)
+                && self.get_cached_title(uri)?.is_none()
+            {
+                uncached.push(uri.to_owned());
+            }
+        }
+        if !uncached.is_empty() {
+            let client = reqwest::Client::builder()
+                .timeout(utils::TITLE_FETCH_TIMEOUT)
+                .build()?;
+            let pb = utils::get_progress_bar(uncached.len() as u64, "Fetching titles...")?;
+            let (client, pb) = (&client, &pb);
+            let titles = stream::iter(&uncached)
+                .map(|uri| async move {
+                    let title = fetch_title(client, uri).await;
+                    pb.inc(1);
+                    (uri, title)
+                })
+                .buffer_unordered(utils::TITLE_FETCH_CONCURRENCY)
+                .collect::<Vec<_>>()
+                .await;
+            // Failures aren't cached, so they're tried again next time
+            for (uri, title) in titles {
+                if let Some(title) = title {
+                    self.cache_title(uri, &title)?;
+                }
+            }
+            pb.finish_with_message("Fetched titles");
+        }
+        for annotation in annotations.iter_mut().filter(|a| has_no_title(a)) {
+            let title = self
+                .get_cached_title(&annotation.uri)?
+                .unwrap_or_else(|| annotation.uri.to_owned());
+            match &mut annotation.document {
+                Some(document) => document.title = vec![title],
+                None => {
+                    annotation.document = Some(Document {
+                        title: vec![title],
+                        dc: None,
+                        highwire: None,
+                        link: Vec::new(),
+                    })
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Search annotations on Hypothesis, retrying on network and server errors.
+    /// Like `search_annotations_return_all`, `query.search_after` is updated to the last annotation found.
+    /// Retries are reported above the progress bar `pb`
+    async fn search_annotations(
+        &self,
+        query: &mut SearchQuery,
+        pb: &indicatif::ProgressBar,
+    ) -> color_eyre::Result<Vec<Annotation>> {
+        let (annotations, searched_query) = utils::retry(self.config.max_retries(), pb, || {
+            let mut query = query.clone();
+            async move {
+                let annotations = self.api.search_annotations_return_all(&mut query).await?;
+                Ok::<_, color_eyre::Report>((annotations, query))
+            }
+        })
+        .await?;
+        *query = searched_query;
+        Ok(annotations)
+    }
+
+    /// Remove the tag from `user_tags` from an annotation, if it was only added locally
+    fn without_local_user_tag(&self, mut annotation: Annotation) -> color_eyre::Result<Annotation> {
+        if let Some(tag) = self.get_local_user_tag(&annotation.id)? {
+            annotation.tags.retain(|t| t != &tag);
+        }
+        Ok(annotation)
+    }
+
+    /// Update annotations on Hypothesis in batches of `update_batch_size`, showing progress
+    ///
+    /// Tags from `user_tags` that were added locally only exist in the local database, so they're left out
+    async fn update_annotations(&self, annotations: &[Annotation]) -> color_eyre::Result<()> {
+        let annotations = annotations
+            .iter()
+            .cloned()
+            .map(|annotation| self.without_local_user_tag(annotation))
+            .collect::<color_eyre::Result<Vec<_>>>()?;
+        let annotations = annotations.as_slice();
+        let batch_size = self
+            .config
+            .update_batch_size
+            .unwrap_or(DEFAULT_UPDATE_BATCH_SIZE)
+            .max(1);
+        if annotations.len() <= batch_size {
+            utils::retry(
+                self.config.max_retries(),
+                &indicatif::ProgressBar::hidden(),
+                || self.api.update_annotations(annotations),
+            )
+            .await?;
+            return Ok(());
+        }
+        let pb = utils::get_progress_bar(annotations.len() as u64, "Updating annotations...")?;
+        for batch in annotations.chunks(batch_size) {
+            utils::retry(self.config.max_retries(), &pb, || {
+                self.api.update_annotations(batch)
+            })
+            .await?;
+            pb.inc(batch.len() as u64);
+        }
+        pb.finish_with_message(format!("Updated {} annotations", annotations.len()));
+        Ok(())
+    }
+
     async fn add_tags(
         &self,
         annotations: Vec<Annotation>,
@@ -427,18 +1435,17 @@ impl Gooseberry {
             tags.len(),
             annotations.len()
         );
-        self.api
-            .update_annotations(
-                &annotations
-                    .clone()
-                    .into_iter()
-                    .map(|mut a| {
-                        a.tags.extend_from_slice(&tags);
-                        a
-                    })
-                    .collect::<Vec<_>>(),
-            )
-            .await?;
+        self.update_annotations(
+            &annotations
+                .clone()
+                .into_iter()
+                .map(|mut a| {
+                    a.tags.extend_from_slice(&tags);
+                    a
+                })
+                .collect::<Vec<_>>(),
+        )
+        .await?;
 
         self.sync().await?;
 
@@ -463,18 +1470,31 @@ impl Gooseberry {
             tags.len(),
             annotations.len()
         );
-        self.api
-            .update_annotations(
-                &annotations
-                    .clone()
-                    .into_iter()
-                    .map(|mut a| {
-                        a.tags.retain(|t| tags.iter().all(|tag| t != tag));
-                        a
-                    })
-                    .collect::<Vec<_>>(),
-            )
-            .await?;
+        self.record_journal(&Journal::TagsRemoved(
+            annotations
+                .iter()
+                .map(|a| {
+                    let removed = a
+                        .tags
+                        .iter()
+                        .filter(|t| tags.contains(t))
+                        .cloned()
+                        .collect();
+                    (a.id.to_owned(), removed)
+                })
+                .collect(),
+        ))?;
+        self.update_annotations(
+            &annotations
+                .clone()
+                .into_iter()
+                .map(|mut a| {
+                    a.tags.retain(|t| tags.iter().all(|tag| t != tag));
+                    a
+                })
+                .collect::<Vec<_>>(),
+        )
+        .await?;
         self.sync().await?;
         Ok(())
     }
@@ -512,7 +1532,166 @@ impl Gooseberry {
         Ok(())
     }
 
-    /// Delete filtered annotations from gooseberry (by adding an ignore tag) or also from Hypothesis
+    /// Replace the tag `old` with `new` in all annotations tagged with `old`
+    pub async fn rename_tag(&self, old: &str, new: &str, dry_run: bool) -> color_eyre::Result<()> {
+        if old == EMPTY_TAG || new == EMPTY_TAG {
+            let error: color_eyre::Result<()> = Err(Apologize::ReservedTag {
+                tag: EMPTY_TAG.to_owned(),
+            }
+            .into());
+            return error.suggestion("Add tags to untagged annotations with `gooseberry tag`");
+        }
+        if old == new {
+            println!("Nothing to rename");
+            return Ok(());
+        }
+        let annotations = self
+            .get_tagged_annotations(old)?
+            .into_iter()
+            .filter(|id| !id.is_empty())
+            .map(|id| self.get_annotation(&id))
+            .collect::<color_eyre::Result<Vec<_>>>()?;
+        if dry_run {
+            let num_with_new = annotations
+                .iter()
+                .filter(|a| a.tags.iter().any(|t| t == new))
+                .count();
+            println!(
+                "Would rename {:?} to {:?} in {} annotation(s), {} of which already have {:?}",
+                old,
+                new,
+                annotations.len(),
+                num_with_new,
+                new
+            );
+            return Ok(());
+        }
+        println!(
+            "Renaming {:?} to {:?} in {} annotation(s)",
+            old,
+            new,
+            annotations.len()
+        );
+        let annotations: Vec<_> = annotations
+            .into_iter()
+            .map(|mut a| {
+                a.tags.retain(|t| t != old);
+                // Don't duplicate the new tag
+                if !a.tags.iter().any(|t| t == new) {
+                    a.tags.push(new.to_owned());
+                }
+                a
+            })
+            .collect();
+        self.update_annotations(&annotations).await?;
+        self.sync().await?;
+        Ok(())
+    }
+
+    /// Tag annotations using the `tag_rules` configuration option.
+    /// Each annotation gets the tags of every rule it matches which it doesn't already have.
+    pub async fn apply_rules(&self, annotations: Vec<Annotation>) -> color_eyre::Result<()> {
+        if self.config.tag_rules.is_empty() {
+            println!("No tag rules defined in the config");
+            return Ok(());
+        }
+        let mut num_tags = 0;
+        let annotations: Vec<_> = annotations
+            .into_iter()
+            .filter_map(|mut a| {
+                let num_tags_before = a.tags.len();
+                for rule in self.config.tag_rules.iter().filter(|r| r.matches(&a)) {
+                    for tag in &rule.add_tags {
+                        if !a.tags.contains(tag) {
+                            a.tags.push(tag.to_owned());
+                        }
+                    }
+                }
+                if a.tags.len() > num_tags_before {
+                    num_tags += a.tags.len() - num_tags_before;
+                    Some(a)
+                } else {
+                    None
+                }
+            })
+            .collect();
+        if annotations.is_empty() {
+            println!("All matching annotations already have their rule tags.");
+            return Ok(());
+        }
+        println!(
+            "Adding {} tag(s) to {} annotation(s)",
+            num_tags,
+            annotations.len()
+        );
+        self.update_annotations(&annotations).await?;
+        self.sync().await?;
+        Ok(())
+    }
+
+    /// List the annotations that `delete` would delete, one per line with ID, URI, and highlight
+    pub fn delete_preview(annotations: &[Annotation]) {
+        for annotation in annotations {
+            let quote = utils::get_quotes(annotation).join(" ");
+            let highlight = if quote.trim().is_empty() {
+                &annotation.text
+            } else {
+                &quote
+            };
+            println!(
+                "{}\t{}\t{}",
+                annotation.id,
+                annotation.uri,
+                truncate_text(
+                    &highlight.split_whitespace().collect::<Vec<_>>().join(" "),
+                    80
+                )
+            );
+        }
+        println!("Would delete {} annotations", annotations.len());
+    }
+
+    /// Hide filtered annotations from the knowledge base by tagging them with the first of the `ignore_tags`
+    /// (that isn't a prefix pattern)
+    pub async fn soft_delete(
+        &self,
+        annotations: Vec<Annotation>,
+        force: bool,
+    ) -> color_eyre::Result<()> {
+        let ignore_tag = match self
+            .config
+            .ignore_tags
+            .iter()
+            .flatten()
+            .find(|tag| !tag.trim().is_empty() && !tag.ends_with('*'))
+        {
+            Some(tag) => tag.to_owned(),
+            None => {
+                let error: color_eyre::Result<()> = Err(Apologize::ConfigError {
+                    message: "No ignore_tags set to soft-delete annotations with".into(),
+                }
+                .into());
+                return error.suggestion("Set tags to ignore with `gooseberry config kb ignore`");
+            }
+        };
+        if !annotations.is_empty()
+            && (force
+                || utils::confirm(
+                    &format!(
+                        "Tag {} annotations with {:?}?",
+                        annotations.len(),
+                        ignore_tag
+                    ),
+                    false,
+                    "Use --force to skip the confirmation",
+                )?)
+        {
+            self.tag(annotations, false, Some(vec![ignore_tag])).await?;
+        }
+        Ok(())
+    }
+
+    /// Delete filtered annotations from gooseberry and from Hypothesis
     pub async fn delete(
         &self,
         annotations: Vec<Annotation>,
@@ -521,24 +1700,100 @@ impl Gooseberry {
         let num_annotations = annotations.len();
         if !annotations.is_empty()
             && (force
-                || Confirm::new()
-                    .with_prompt(&format!("Delete {} annotations?", num_annotations))
-                    .default(false)
-                    .interact()?)
+                || utils::confirm(
+                    &format!("Delete {} annotations?", num_annotations),
+                    false,
+                    "Use --force to skip the confirmation",
+                )?)
         {
             let ids = annotations
                 .iter()
                 .map(|a| a.id.to_owned())
                 .collect::<Vec<_>>();
+            let annotations = annotations
+                .into_iter()
+                .map(|annotation| self.without_local_user_tag(annotation))
+                .collect::<color_eyre::Result<Vec<_>>>()?;
+            utils::retry(
+                self.config.max_retries(),
+                &indicatif::ProgressBar::hidden(),
+                || self.api.delete_annotations(&ids),
+            )
+            .await?;
+            // Only once they're gone from Hypothesis, so that undo doesn't duplicate them
+            self.record_journal(&Journal::Deleted(annotations))?;
             self.delete_annotations(&ids)?;
-            self.api.delete_annotations(&ids).await?;
             println!("{} annotations deleted", num_annotations);
         }
         Ok(())
     }
 
+    /// Undo the last `delete` or `tag --delete` recorded in the journal.
+    /// Hypothesis can't restore deleted annotations, so they're created again with new IDs.
+    /// Annotations by other users would be recreated as yours, so they're skipped
+    pub async fn undo(&self) -> color_eyre::Result<()> {
+        match self.get_journal()? {
+            None => {
+                println!("Nothing to undo");
+                return Ok(());
+            }
+            Some(Journal::Deleted(annotations)) => {
+                let (annotations, others): (Vec<_>, Vec<_>) = annotations
+                    .into_iter()
+                    .partition(|annotation| annotation.user == self.api.user);
+                if !others.is_empty() {
+                    eprintln!(
+                        "Warning: skipping {} annotation(s) by other users, which can't be recreated",
+                        others.len()
+                    );
+                }
+                let input_annotations = annotations
+                    .into_iter()
+                    .filter(|annotation| !annotation.uri.is_empty())
+                    .map(|annotation| {
+                        let group_id = annotation.group.to_owned();
+                        to_input_annotation(annotation, &group_id)
+                    })
+                    .collect::<color_eyre::Result<Vec<_>>>()?;
+                self.api.create_annotations(&input_annotations).await?;
+                println!(
+                    "Recreated {} annotation(s), note that they have new IDs",
+                    input_annotations.len()
+                );
+            }
+            Some(Journal::TagsRemoved(removed_tags)) => {
+                let mut annotations = Vec::new();
+                for (id, tags) in removed_tags {
+                    // Skip annotations deleted since
+                    if let Ok(mut annotation) = self.get_annotation(&id) {
+                        for tag in tags {
+                            if !annotation.tags.contains(&tag) {
+                                annotation.tags.push(tag);
+                            }
+                        }
+                        annotations.push(annotation);
+                    }
+                }
+                self.update_annotations(&annotations).await?;
+                println!("Restored tags of {} annotation(s)", annotations.len());
+            }
+        }
+        self.clear_journal()?;
+        self.sync().await?;
+        Ok(())
+    }
+
     /// View optionally filtered annotations in the terminal
-    pub fn view(&mut self, filters: Filters, id: Option<String>) -> color_eyre::Result<()> {
+    /// With `threaded`, replies follow their parent annotation and are indented as quotes
+    pub fn view(
+        &mut self,
+        filters: Filters,
+        id: Option<String>,
+        threaded: bool,
+        stats_footer: bool,
+        group_by: Option<SectionHeader>,
+        limit: Limit,
+    ) -> color_eyre::Result<()> {
         if self.config.annotation_template.is_none() {
             self.config.set_annotation_template()?;
         }
@@ -548,7 +1803,7 @@ impl Gooseberry {
                 .get_annotation(&id)
                 .suggestion("Are you sure this is a valid and existing annotation ID?")?;
             let markdown = hbs.render(
-                "annotation",
+                &self.config.annotation_template_for(&annotation.tags),
                 &AnnotationTemplate::from_annotation(annotation, &self.config.hypothesis_groups),
             )?;
             bat::PrettyPrinter::new()
@@ -558,25 +1813,66 @@ impl Gooseberry {
                 .map_err(|_| eyre!("Bat printing error"))?;
             return Ok(());
         }
-        let inputs: Vec<_> = self
-            .filter_annotations(filters)?
+        let mut annotations = self.filter_annotations(filters)?;
+        if self.config.skip_empty_annotations {
+            utils::skip_empty_annotations(&mut annotations, |a| a);
+        }
+        // Before rendering, which is the slow part
+        limit_annotations(&mut annotations, limit);
+        let footer = stats_footer.then(|| utils::AnnotationStats::new(&annotations).to_markdown());
+        if let Some(group_by) = group_by {
+            let annotations: Vec<_> = annotations
+                .into_iter()
+                .map(|a| AnnotationTemplate::from_annotation(a, &self.config.hypothesis_groups))
+                .collect();
+            let header_style = self.config.view_header_style()?;
+            let header_prefix = self
+                .config
+                .view_header_prefix
+                .as_deref()
+                .unwrap_or(DEFAULT_VIEW_HEADER_PREFIX);
+            for (section, annotations) in group_annotations_by_section(group_by, &annotations) {
+                println!(
+                    "\n{}\n",
+                    header_style.apply_to(format!("{} {}", header_prefix, section))
+                );
+                let inputs = annotations
+                    .iter()
+                    .map(|a| {
+                        hbs.render(&self.config.annotation_template_for(&a.annotation.tags), a)
+                    })
+                    .collect::<Result<Vec<_>, RenderError>>()?;
+                print_markdown(&inputs)?;
+            }
+            return print_markdown(&footer.into_iter().collect::<Vec<_>>());
+        }
+        let annotations = if threaded {
+            utils::thread_annotations(annotations)
+        } else {
+            annotations.into_iter().map(|a| (0, a)).collect()
+        };
+        let mut inputs: Vec<_> = annotations
             .into_iter()
-            .map(|annotation| {
-                hbs.render(
-                    "annotation",
+            .map(|(depth, annotation)| {
+                let markdown = hbs.render(
+                    &self.config.annotation_template_for(&annotation.tags),
                     &AnnotationTemplate::from_annotation(
                         annotation,
                         &self.config.hypothesis_groups,
                     ),
-                )
+                )?;
+                if depth == 0 {
+                    return Ok(markdown);
+                }
+                let indent = "> ".repeat(depth);
+                Ok(markdown
+                    .lines()
+                    .map(|line| format!("{}{}\n", indent, line))
+                    .collect())
             })
-            .collect::<Result<_, _>>()?;
-        bat::PrettyPrinter::new()
-            .language("markdown")
-            .inputs(inputs.iter().map(|i| bat::Input::from_bytes(i.as_bytes())))
-            .print()
-            .map_err(|_| eyre!("Bat printing error"))?;
-        Ok(())
+            .collect::<Result<_, RenderError>>()?;
+        inputs.extend(footer);
+        print_markdown(&inputs)
     }
 
     pub fn uri(&self, annotations: Vec<Annotation>, ids: Vec<String>) -> color_eyre::Result<()> {
@@ -591,14 +1887,83 @@ impl Gooseberry {
         Ok(())
     }
 
+    /// Print each source URI with the number of annotations on it, most annotated first
+    pub fn uris(annotations: Vec<Annotation>) {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for annotation in annotations {
+            *counts.entry(utils::clean_uri(&annotation.uri)).or_default() += 1;
+        }
+        let mut counts: Vec<_> = counts.into_iter().collect();
+        counts.sort_by(|(uri_a, count_a), (uri_b, count_b)| {
+            count_b.cmp(count_a).then_with(|| uri_a.cmp(uri_b))
+        });
+        for (uri, count) in counts {
+            println!("{} ({})", uri, count);
+        }
+    }
+
+    /// Print all tags with the number of annotations tagged with them,
+    /// optionally as a tree of nested tags in which counts include children.
+    /// Untagged annotations aren't listed
+    pub fn tags(&self, tree: bool) -> color_eyre::Result<()> {
+        let nested_tag = self
+            .config
+            .nested_tag
+            .as_deref()
+            .unwrap_or(DEFAULT_NESTED_TAG);
+        let mut root = TagNode::default();
+        for item in self.tag_to_annotations()?.iter() {
+            let (tag, ids) = item?;
+            let tag = std::str::from_utf8(&tag)?;
+            if tag == EMPTY_TAG {
+                continue;
+            }
+            let ids = utils::split_ids(&ids)?
+                .into_iter()
+                .filter(|id| !id.is_empty())
+                .collect::<Vec<_>>();
+            if ids.is_empty() {
+                continue;
+            }
+            let parts: Vec<&str> = if tree {
+                tag.split(nested_tag).collect()
+            } else {
+                vec![tag]
+            };
+            let mut node = &mut root;
+            for part in parts {
+                node = node.children.entry(part.to_owned()).or_default();
+                node.annotation_ids.extend(ids.iter().cloned());
+            }
+        }
+        root.print(0);
+        Ok(())
+    }
+
+    /// Print all tags in the database one per line, for shell completions.
+    /// Opens the database directly, since completions shouldn't wait for Hypothesis
+    fn complete_tags(config: &GooseberryConfig) -> color_eyre::Result<()> {
+        let db = Self::get_db(&config.db_dir)?;
+        for item in db.open_tree("tag_to_annotations")?.iter() {
+            let (tag, ids) = item?;
+            let tag = std::str::from_utf8(&tag)?;
+            if tag == EMPTY_TAG || utils::split_ids(&ids)?.iter().all(|id| id.is_empty()) {
+                continue;
+            }
+            println!("{}", tag);
+        }
+        Ok(())
+    }
+
     /// Removes all `sled` trees
     /// Deletes everything in the `db_dir`
     pub fn clear(&self, force: bool) -> color_eyre::Result<()> {
         if force
-            || Confirm::new()
-                .with_prompt("Clear all gooseberry data?")
-                .default(false)
-                .interact()?
+            || utils::confirm(
+                "Clear all gooseberry data?",
+                false,
+                "Use --force to skip the confirmation",
+            )?
         {
             for path in fs::read_dir(&self.config.db_dir)? {
                 let path = path?.path();
@@ -1,5 +1,5 @@
 use std::borrow::Cow;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
 use dialoguer::console::style;
@@ -17,6 +17,9 @@ use crate::gooseberry::knowledge_base::AnnotationTemplate;
 use crate::gooseberry::Gooseberry;
 use crate::utils;
 
+/// Maximum number of tags suggested when adding tags
+const NUM_SUGGESTED_TAGS: usize = 10;
+
 /// searchable annotation information
 #[derive(Debug)]
 pub struct SearchAnnotation {
@@ -92,12 +95,14 @@ impl Gooseberry {
                 "shift-right:accept",
                 "shift-up:accept",
                 "shift-down:accept",
+                "ctrl-y:accept",
+                "ctrl-o:accept",
                 "Enter:accept"
             ])
             .exact(!fuzzy)
             .header(Some("Arrow keys to scroll, Tab to toggle selection, Ctrl-A to select all, Esc to abort\n\
             Enter to add a tag, Shift-Left to delete a tag, Shift-Right to delete annotation\n\
-            Shift-Down to make knowledge-base files, Shift-Up to print the set of URIs"))
+            Shift-Down to make knowledge-base files, Shift-Up to print the set of URIs, Ctrl-Y to copy the URIs, Ctrl-O to open in the browser"))
             .multi(true)
             .reverse(true)
             .build()
@@ -105,12 +110,7 @@ impl Gooseberry {
 
         let (tx_item, rx_item): (SkimItemSender, SkimItemReceiver) = unbounded();
         for annotation in &annotations {
-            let mut title = String::from("Untitled document");
-            if let Some(document) = &annotation.document {
-                if !document.title.is_empty() {
-                    title = document.title[0].to_owned();
-                }
-            }
+            let title = utils::get_title(annotation);
             let mut highlight = format!(
                 "{}",
                 style(
@@ -149,7 +149,7 @@ impl Gooseberry {
             let _ = tx_item.send(Arc::new(SearchAnnotation {
                 highlight,
                 markdown: hbs.render(
-                    "annotation",
+                    &self.config.annotation_template_for(&annotation.tags),
                     &AnnotationTemplate::from_annotation(
                         annotation.clone(),
                         &self.config.hypothesis_groups,
@@ -199,11 +199,29 @@ impl Gooseberry {
                         .with_prompt("Also make index file?")
                         .default(true)
                         .interact()?;
-                    self.make(annotations, clear, true, true, index)?;
+                    self.make(annotations, clear, true, true, index, None)?;
                 }
                 Key::ShiftUp => {
                     self.uri(annotations, Vec::new())?;
                 }
+                Key::Ctrl('y') => {
+                    let mut seen = HashSet::new();
+                    let uris: Vec<_> = annotations
+                        .into_iter()
+                        .map(|a| a.uri)
+                        .filter(|uri| seen.insert(uri.to_owned()))
+                        .collect();
+                    println!("Copying {} URI(s) to the clipboard", uris.len());
+                    utils::copy_to_clipboard(uris.join("\n"))?;
+                }
+                Key::Ctrl('o') => {
+                    for annotation in &annotations {
+                        // The in-context link shows the annotation on the page, not every annotation has one
+                        let link = annotation.links.get("incontext").unwrap_or(&annotation.uri);
+                        webbrowser::open(link)?;
+                    }
+                    println!("Opened {} annotation(s) in the browser", annotations.len());
+                }
                 _ => (),
             }
             Ok(())
@@ -246,9 +264,15 @@ impl Gooseberry {
                 .collect()
         };
         tags.sort();
+        if add {
+            // Put suggestions first
+            let suggested_tags = self.suggest_tags(annotations)?;
+            tags.retain(|tag| !suggested_tags.contains(tag));
+            tags = suggested_tags.into_iter().chain(tags).collect();
+        }
 
         let mut message = if add {
-            "Select tags or create new comma-separated tags to add".to_owned()
+            "Select tags (suggestions first) or create new comma-separated tags to add".to_owned()
         } else {
             "Select tags to remove".to_owned()
         };
@@ -292,6 +316,40 @@ impl Gooseberry {
         }
     }
 
+    /// Tags that other annotations often have together with the tags of the given annotations,
+    /// most frequent first
+    fn suggest_tags(&self, annotations: &[Annotation]) -> color_eyre::Result<Vec<String>> {
+        let current_tags: HashSet<&str> = annotations
+            .iter()
+            .flat_map(|a| a.tags.iter().map(String::as_str))
+            .collect();
+        let selected_ids: HashSet<&str> = annotations.iter().map(|a| a.id.as_str()).collect();
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for tag in &current_tags {
+            for id in self.get_tagged_annotations(tag)? {
+                if id.is_empty() || selected_ids.contains(id.as_str()) {
+                    continue;
+                }
+                if let Ok(co_tags) = self.get_annotation_tags(&id) {
+                    for co_tag in co_tags {
+                        if !current_tags.contains(co_tag.as_str()) {
+                            *counts.entry(co_tag).or_default() += 1;
+                        }
+                    }
+                }
+            }
+        }
+        let mut suggested_tags: Vec<_> = counts.into_iter().collect();
+        suggested_tags.sort_by(|(tag_a, count_a), (tag_b, count_b)| {
+            count_b.cmp(count_a).then_with(|| tag_a.cmp(tag_b))
+        });
+        Ok(suggested_tags
+            .into_iter()
+            .take(NUM_SUGGESTED_TAGS)
+            .map(|(tag, _)| tag)
+            .collect())
+    }
+
     /// Makes a skim search window for given annotations from an external group
     pub fn search_group(
         &self,
@@ -333,7 +391,7 @@ impl Gooseberry {
             let _ = tx_item.send(Arc::new(SearchAnnotation {
                 highlight,
                 markdown: hbs.render(
-                    "annotation",
+                    &self.config.annotation_template_for(&annotation.tags),
                     &AnnotationTemplate::from_annotation(
                         annotation.clone(),
                         &self.config.hypothesis_groups,
@@ -0,0 +1,202 @@
+use std::io;
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use hypothesis::annotations::Annotation;
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::Terminal;
+
+use crate::gooseberry::knowledge_base::AnnotationTemplate;
+use crate::gooseberry::Gooseberry;
+use crate::utils;
+
+const TUI_HELP: &str =
+    " Up/Down to move, PgUp/PgDn to scroll, t to add a tag, T to remove a tag, d to delete, o to open in browser, q to quit ";
+
+/// Actions which leave the TUI to act on the selected annotation
+enum TuiAction {
+    AddTag,
+    DeleteTag,
+    Delete,
+}
+
+/// An annotation as shown in the TUI
+struct TuiAnnotation {
+    /// Single line summary shown in the list
+    summary: String,
+    /// Annotation rendered with the annotation template
+    markdown: String,
+    /// Link opened in the browser
+    link: String,
+}
+
+/// Runs the TUI, starting at the `selected` annotation, until the user quits or chooses an action.
+/// Returns the chosen action and the index of the selected annotation.
+fn run_tui(
+    items: &[TuiAnnotation],
+    selected: usize,
+) -> color_eyre::Result<Option<(TuiAction, usize)>> {
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+    let result = tui_loop(&mut terminal, items, selected);
+    // Restore the terminal even if the loop failed
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    result
+}
+
+fn tui_loop(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    items: &[TuiAnnotation],
+    selected: usize,
+) -> color_eyre::Result<Option<(TuiAction, usize)>> {
+    let mut state = ListState::default();
+    state.select(Some(selected.min(items.len() - 1)));
+    let mut scroll: u16 = 0;
+    loop {
+        let selected = state.selected().unwrap_or(0);
+        terminal.draw(|frame| {
+            let chunks = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .split(frame.size());
+            let list = List::new(
+                items
+                    .iter()
+                    .map(|item| ListItem::new(item.summary.as_str()))
+                    .collect::<Vec<_>>(),
+            )
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!(" Annotations ({}) ", items.len()))
+                    .title_bottom(TUI_HELP),
+            )
+            .highlight_style(
+                Style::default()
+                    .fg(Color::Yellow)
+                    .add_modifier(Modifier::REVERSED),
+            );
+            frame.render_stateful_widget(list, chunks[0], &mut state);
+            let preview = Paragraph::new(items[selected].markdown.as_str())
+                .block(Block::default().borders(Borders::ALL))
+                .wrap(Wrap { trim: false })
+                .scroll((scroll, 0));
+            frame.render_widget(preview, chunks[1]);
+        })?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(None),
+                KeyCode::Down | KeyCode::Char('j') => {
+                    state.select(Some((selected + 1).min(items.len() - 1)));
+                    scroll = 0;
+                }
+                KeyCode::Up | KeyCode::Char('k') => {
+                    state.select(Some(selected.saturating_sub(1)));
+                    scroll = 0;
+                }
+                KeyCode::PageDown => scroll = scroll.saturating_add(10),
+                KeyCode::PageUp => scroll = scroll.saturating_sub(10),
+                KeyCode::Char('t') => return Ok(Some((TuiAction::AddTag, selected))),
+                KeyCode::Char('T') => return Ok(Some((TuiAction::DeleteTag, selected))),
+                KeyCode::Char('d') => return Ok(Some((TuiAction::Delete, selected))),
+                KeyCode::Char('o') => {
+                    // Opening can fail on headless machines, nothing to do then
+                    let _ = webbrowser::open(&items[selected].link);
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+/// ## TUI
+/// `ratatui` terminal interface functions
+impl Gooseberry {
+    /// Browse annotations in a two-pane terminal interface:
+    /// the list of annotations on the left, the rendered markdown of the selected one on the right.
+    /// After each action the TUI comes back, with the changed annotation reloaded from the database
+    pub async fn view_tui(&mut self, mut annotations: Vec<Annotation>) -> color_eyre::Result<()> {
+        if annotations.is_empty() {
+            println!("No matching annotations");
+            return Ok(());
+        }
+        if self.config.annotation_template.is_none() {
+            self.config.set_annotation_template()?;
+        }
+        let mut selected = 0;
+        while !annotations.is_empty() {
+            let items = self.tui_items(&annotations)?;
+            let Some((action, index)) = run_tui(&items, selected)? else {
+                break;
+            };
+            selected = index;
+            let annotation = vec![annotations[index].clone()];
+            match action {
+                TuiAction::AddTag => {
+                    let tags = self.search_tags(&annotation, true)?;
+                    self.tag(annotation, false, Some(tags)).await?;
+                    annotations[index] = self.get_annotation(&annotations[index].id)?;
+                }
+                TuiAction::DeleteTag => {
+                    let tags = self.search_tags(&annotation, false)?;
+                    self.tag(annotation, true, Some(tags)).await?;
+                    annotations[index] = self.get_annotation(&annotations[index].id)?;
+                }
+                TuiAction::Delete => {
+                    if utils::confirm(
+                        "Delete this annotation?",
+                        false,
+                        "Use `gooseberry delete` to delete annotations non-interactively",
+                    )? {
+                        self.delete(annotation, true).await?;
+                        annotations.remove(index);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Summaries and rendered markdown of the annotations shown in the TUI
+    fn tui_items(&self, annotations: &[Annotation]) -> color_eyre::Result<Vec<TuiAnnotation>> {
+        let hbs = self.get_handlebars()?;
+        annotations
+            .iter()
+            .map(|annotation| {
+                let annotation_template = AnnotationTemplate::from_annotation(
+                    annotation.clone(),
+                    &self.config.hypothesis_groups,
+                );
+                let quote = utils::get_quotes(annotation).join(" ");
+                let summary = if !quote.is_empty() {
+                    quote
+                } else if !annotation.text.is_empty() {
+                    annotation.text.to_owned()
+                } else {
+                    annotation_template.title.to_owned()
+                };
+                Ok(TuiAnnotation {
+                    summary: summary.replace('\n', " "),
+                    markdown: hbs.render(
+                        &self.config.annotation_template_for(&annotation.tags),
+                        &annotation_template,
+                    )?,
+                    link: annotation_template.incontext,
+                })
+            })
+            .collect()
+    }
+}
@@ -1,8 +1,15 @@
 use chrono::{DateTime, Local, Utc};
 use chrono_english::{parse_date_string, Dialect};
 use color_eyre::Section;
-use dialoguer::{theme, Editor, Input};
+use dialoguer::{theme, Confirm, Editor, Input};
 use hypothesis::annotations::Selector;
+use hypothesis::errors::HypothesisError;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::future::Future;
+use std::path::Path;
+use std::process::Command;
+use std::sync::LazyLock;
 use std::time::Duration;
 use url::Url;
 
@@ -34,6 +41,35 @@ pub fn join_ids(index_list: &[String]) -> color_eyre::Result<Vec<u8>> {
     Ok(index_list.join(";").as_bytes().to_vec())
 }
 
+/// Environment variable that makes gooseberry fail instead of prompting for missing configuration
+pub const NONINTERACTIVE_ENV: &str = "GOOSEBERRY_NONINTERACTIVE";
+
+/// Fails with an error about the missing `setting` (suggesting how to set it)
+/// instead of prompting for it if `GOOSEBERRY_NONINTERACTIVE` is set, e.g. in scripts and CI
+pub fn require_interactive(setting: &str, suggestion: &str) -> color_eyre::Result<()> {
+    let non_interactive =
+        env::var(NONINTERACTIVE_ENV).is_ok_and(|value| !matches!(value.trim(), "" | "0" | "false"));
+    if non_interactive {
+        return Err(Apologize::ConfigError {
+            message: format!(
+                "{} not set, and {} is set so gooseberry can't ask for it",
+                setting, NONINTERACTIVE_ENV
+            ),
+        })
+        .suggestion(suggestion.to_owned());
+    }
+    Ok(())
+}
+
+/// Asks a yes/no question, failing with `suggestion` instead if `GOOSEBERRY_NONINTERACTIVE` is set
+pub fn confirm(prompt: &str, default: bool, suggestion: &str) -> color_eyre::Result<bool> {
+    require_interactive(&format!("Answer to {:?}", prompt), suggestion)?;
+    Ok(Confirm::with_theme(&theme::ColorfulTheme::default())
+        .with_prompt(prompt)
+        .default(default)
+        .interact()?)
+}
+
 /// Takes user input from terminal, optionally has a default and optionally displays it.
 pub fn user_input(
     message: &str,
@@ -72,6 +108,154 @@ pub fn external_editor_input(default: Option<&str>, extension: &str) -> color_ey
         .suggestion("Make sure to save next time!")
 }
 
+/// Opens a file in the external editor, editing it in place
+pub fn external_editor_file(path: &Path) -> color_eyre::Result<()> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| {
+            if cfg!(windows) {
+                "notepad".to_owned()
+            } else {
+                "vi".to_owned()
+            }
+        });
+    let mut editor = editor.split_whitespace();
+    let status = Command::new(editor.next().ok_or(Apologize::EditorError)?)
+        .args(editor)
+        .arg(path)
+        .status()
+        .suggestion("Set your default editor using the $EDITOR or $VISUAL environment variables")?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Apologize::EditorError).suggestion(
+            "Set your default editor using the $EDITOR or $VISUAL environment variables",
+        )
+    }
+}
+
+/// Copies text to the clipboard.
+/// On Linux (X11 and Wayland) the text is served by this process and lost when it exits,
+/// so this waits until something else is copied
+#[cfg(all(
+    unix,
+    not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+))]
+pub fn copy_to_clipboard(text: String) -> color_eyre::Result<()> {
+    use arboard::SetExtLinux;
+    println!("Waiting until something else is copied to keep the clipboard contents");
+    arboard::Clipboard::new()?.set().wait().text(text)?;
+    Ok(())
+}
+
+/// Copies text to the clipboard
+#[cfg(not(all(
+    unix,
+    not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+)))]
+pub fn copy_to_clipboard(text: String) -> color_eyre::Result<()> {
+    arboard::Clipboard::new()?.set_text(text)?;
+    Ok(())
+}
+
+/// Delay before the first retry of a failed Hypothesis API call, doubled for each further retry
+pub static RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// A 5xx status code, as named in the error pages of servers and proxies
+static SERVER_ERROR_STATUS: LazyLock<regex::Regex> =
+    LazyLock::new(|| regex::Regex::new(r"\b5\d\d\b").unwrap());
+
+/// Checks if an error is worth retrying: a timeout, a connection error, or a server (5xx) error.
+/// Authorization and other client (4xx) errors are not retried.
+pub fn is_transient_error(error: &eyre::Report) -> bool {
+    error.chain().any(|cause| {
+        if let Some(error) = cause.downcast_ref::<reqwest::Error>() {
+            match error.status() {
+                Some(status) => status.is_server_error(),
+                None => error.is_timeout() || error.is_connect(),
+            }
+        } else if let Some(HypothesisError::APIError { raw_text, .. }) =
+            cause.downcast_ref::<HypothesisError>()
+        {
+            // The Hypothesis client doesn't keep the HTTP status. Client errors come with a JSON
+            // failure reason, while server errors (e.g. a 502 from its proxy) come with an error page naming the status
+            serde_json::from_str::<serde_json::Value>(raw_text).is_err()
+                && SERVER_ERROR_STATUS.is_match(raw_text)
+        } else {
+            false
+        }
+    })
+}
+
+/// Runs `operation`, retrying it up to `max_retries` times with exponential backoff
+/// if it fails with a transient error. Retries are reported above the progress bar `pb`
+pub async fn retry<T, E, F, Fut>(
+    max_retries: usize,
+    pb: &indicatif::ProgressBar,
+    mut operation: F,
+) -> color_eyre::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: Into<eyre::Report>,
+{
+    let mut delay = RETRY_DELAY;
+    let mut retries = 0;
+    loop {
+        match operation().await.map_err(Into::into) {
+            Err(error) if retries < max_retries && is_transient_error(&error) => {
+                retries += 1;
+                pb.suspend(|| eprintln!("{}, retrying ({}/{})", error, retries, max_retries));
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Registrable domain of a URI (e.g. "wikipedia.org" for "https://en.wikipedia.org/wiki/Rust"),
+/// or its host if it has no known public suffix, or the URI itself if it has no host
+pub fn registrable_domain(uri: &str) -> String {
+    match Url::parse(uri)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_owned))
+    {
+        Some(host) => psl::domain_str(&host).unwrap_or(host.as_str()).to_owned(),
+        None => uri.to_owned(),
+    }
+}
+
+/// Time to wait for a response when calling the Hypothesis API directly
+pub const API_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time to wait for a page when fetching its title
+pub const TITLE_FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Most bytes of a page read when fetching its title
+pub const TITLE_FETCH_MAX_BYTES: usize = 512 * 1024;
+
+/// Number of pages fetched at once when fetching titles
+pub const TITLE_FETCH_CONCURRENCY: usize = 8;
+
+static TITLE_REGEX: LazyLock<regex::Regex> =
+    LazyLock::new(|| regex::Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap());
+
+/// Text of the `<title>` element of an HTML page, with whitespace collapsed and common entities decoded
+pub fn html_title(html: &str) -> Option<String> {
+    let title = TITLE_REGEX.captures(html)?.get(1)?.as_str();
+    let title = title
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&");
+    (!title.is_empty()).then_some(title)
+}
+
 pub fn get_spinner(message: &str) -> color_eyre::Result<indicatif::ProgressBar> {
     let spinner = indicatif::ProgressBar::new_spinner();
     spinner.enable_steady_tick(Duration::from_millis(200));
@@ -84,6 +268,57 @@ pub fn get_spinner(message: &str) -> color_eyre::Result<indicatif::ProgressBar>
     Ok(spinner)
 }
 
+pub fn get_progress_bar(length: u64, message: &str) -> color_eyre::Result<indicatif::ProgressBar> {
+    let progress_bar = indicatif::ProgressBar::new(length);
+    progress_bar.set_style(
+        indicatif::ProgressStyle::default_bar()
+            .template("{bar:40.dim.bold.blue} {pos}/{len} {wide_msg}")?,
+    );
+    progress_bar.set_message(message.to_owned());
+    Ok(progress_bar)
+}
+
+/// Aggregate counts over a set of annotations
+pub struct AnnotationStats {
+    pub num_annotations: usize,
+    pub num_tags: usize,
+    pub num_sources: usize,
+    /// Dates the first and last annotations were created
+    pub date_range: Option<(DateTime<Utc>, DateTime<Utc>)>,
+}
+
+impl AnnotationStats {
+    pub fn new(annotations: &[hypothesis::annotations::Annotation]) -> Self {
+        let tags: HashSet<_> = annotations.iter().flat_map(|a| &a.tags).collect();
+        let sources: HashSet<_> = annotations.iter().map(|a| clean_uri(&a.uri)).collect();
+        let first = annotations.iter().map(|a| a.created).min();
+        let last = annotations.iter().map(|a| a.created).max();
+        Self {
+            num_annotations: annotations.len(),
+            num_tags: tags.len(),
+            num_sources: sources.len(),
+            date_range: first.zip(last),
+        }
+    }
+
+    /// Summary as a markdown section
+    pub fn to_markdown(&self) -> String {
+        let mut summary = format!(
+            "\n---\n\n**{} annotations** with {} distinct tags from {} sources",
+            self.num_annotations, self.num_tags, self.num_sources
+        );
+        if let Some((first, last)) = self.date_range {
+            summary.push_str(&format!(
+                ", created {} to {}",
+                first.format("%F"),
+                last.format("%F")
+            ));
+        }
+        summary.push('\n');
+        summary
+    }
+}
+
 pub fn get_quotes(annotation: &hypothesis::annotations::Annotation) -> Vec<&str> {
     annotation
         .target
@@ -107,6 +342,97 @@ pub fn get_quotes(annotation: &hypothesis::annotations::Annotation) -> Vec<&str>
         .collect::<Vec<_>>()
 }
 
+/// Title of the annotated document, if it has one
+pub fn document_title(annotation: &hypothesis::annotations::Annotation) -> Option<&str> {
+    annotation
+        .document
+        .as_ref()
+        .and_then(|document| document.title.first())
+        .map(String::as_str)
+}
+
+/// Title of the annotated document, or "Untitled document"
+pub fn get_title(annotation: &hypothesis::annotations::Annotation) -> &str {
+    document_title(annotation).unwrap_or("Untitled document")
+}
+
+/// Checks if an annotation has neither a textual body nor any highlighted text
+pub fn is_empty_annotation(annotation: &hypothesis::annotations::Annotation) -> bool {
+    annotation.text.trim().is_empty() && get_quotes(annotation).is_empty()
+}
+
+/// Removes annotations without text or quotes and reports how many were skipped
+pub fn skip_empty_annotations<T>(
+    annotations: &mut Vec<T>,
+    get_annotation: impl Fn(&T) -> &hypothesis::annotations::Annotation,
+) {
+    let num_annotations = annotations.len();
+    annotations.retain(|a| !is_empty_annotation(get_annotation(a)));
+    let num_skipped = num_annotations - annotations.len();
+    if num_skipped == 1 {
+        println!("Skipped 1 empty annotation");
+    } else if num_skipped > 1 {
+        println!("Skipped {} empty annotations", num_skipped);
+    }
+}
+
+/// Checks if a tag is one of the `ignore_tags`, where entries ending in `*` match tags starting with the rest,
+/// e.g. "tmp/*" matches "tmp/draft"
+pub fn is_ignored_tag(tag: &str, ignore_tags: &[String]) -> bool {
+    ignore_tags
+        .iter()
+        .any(|ignore_tag| match ignore_tag.strip_suffix('*') {
+            Some(prefix) => tag.starts_with(prefix),
+            None => tag == ignore_tag,
+        })
+}
+
+/// Checks if a Hypothesis account ID (`acct:username@authority`) belongs to the given user,
+/// which can be either a username or a full account ID
+pub fn is_user(account_id: &str, user: &str) -> bool {
+    account_id == user || username(account_id) == user
+}
+
+/// Username part of a Hypothesis account ID (`acct:username@authority`),
+/// or the account ID itself if it isn't in that form
+pub fn username(account_id: &str) -> &str {
+    account_id
+        .strip_prefix("acct:")
+        .and_then(|account| account.split_once('@'))
+        .map(|(username, _)| username)
+        .filter(|username| !username.is_empty())
+        .unwrap_or(account_id)
+}
+
+/// Orders annotations so that each reply follows its parent, returning each annotation with its depth in the thread.
+/// Annotations whose parent isn't in the list are treated as thread roots, the original order is kept among siblings.
+pub fn thread_annotations(
+    annotations: Vec<hypothesis::annotations::Annotation>,
+) -> Vec<(usize, hypothesis::annotations::Annotation)> {
+    let ids: HashSet<String> = annotations.iter().map(|a| a.id.to_owned()).collect();
+    let mut roots = Vec::new();
+    let mut replies: HashMap<String, Vec<hypothesis::annotations::Annotation>> = HashMap::new();
+    for annotation in annotations {
+        match annotation.references.last() {
+            Some(parent) if ids.contains(parent) => replies
+                .entry(parent.to_owned())
+                .or_default()
+                .push(annotation),
+            _ => roots.push(annotation),
+        }
+    }
+    let mut threaded = Vec::with_capacity(ids.len());
+    // Depth-first, with a stack in reverse order to keep the original order among siblings
+    let mut stack: Vec<_> = roots.into_iter().rev().map(|a| (0, a)).collect();
+    while let Some((depth, annotation)) = stack.pop() {
+        if let Some(children) = replies.remove(&annotation.id) {
+            stack.extend(children.into_iter().rev().map(|a| (depth + 1, a)));
+        }
+        threaded.push((depth, annotation));
+    }
+    threaded
+}
+
 pub fn clean_uri(uri: &str) -> String {
     match Url::parse(uri) {
         Ok(parsed_uri) => {
@@ -123,9 +449,155 @@ pub fn clean_uri(uri: &str) -> String {
     }
 }
 
-/// Converts a URI into something that can be used as a folder/filename
+/// Makes a name (of a tag, source, title, group, etc.) safe to use as a file or folder name,
+/// by removing characters that are reserved on some file systems
+pub fn to_filename(name: &str) -> String {
+    sanitize_filename::sanitize(name)
+}
+
+/// Converts a URI into something that can be used as a folder/filename.
+/// A short hash of the URI is appended so that URIs that differ only in the replaced characters,
+/// like "example.com/a.b" and "example.com/a/b", don't end up in the same file.
 pub fn uri_to_filename(uri: &str) -> String {
-    clean_uri(uri)
-        .replace("://", "_")
-        .replace(['.', '/', ':'], "_")
+    let uri = clean_uri(uri);
+    // FNV-1a, which unlike the standard library's hasher stays the same across Rust versions
+    let hash = uri.bytes().fold(0x811c_9dc5_u32, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    });
+    format!(
+        "{}_{:08x}",
+        uri.replace("://", "_").replace(['.', '/', ':'], "_"),
+        hash
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use eyre::eyre;
+    use hypothesis::errors::APIError;
+
+    use super::*;
+
+    /// An API error as the Hypothesis client returns it, with the body of the response
+    fn api_error(raw_text: &str) -> HypothesisError {
+        HypothesisError::APIError {
+            source: APIError::default(),
+            serde_error: None,
+            raw_text: raw_text.to_owned(),
+        }
+    }
+
+    #[test]
+    fn transient_errors() {
+        // Server errors reach the Hypothesis client as API errors with an error page
+        assert!(is_transient_error(
+            &api_error("<html><h1>502 Bad Gateway</h1></html>").into()
+        ));
+        // Client errors come with a JSON failure reason, or an error page with a 4xx status
+        assert!(!is_transient_error(
+            &api_error(r#"{"status": "failure", "reason": "Invalid API key"}"#).into()
+        ));
+        assert!(!is_transient_error(
+            &api_error("<html><h1>404 Not Found</h1></html>").into()
+        ));
+        // Errors that don't come from the API at all
+        assert!(!is_transient_error(&eyre!("Invalid input")));
+    }
+
+    #[tokio::test]
+    async fn retry_transient_errors() -> color_eyre::Result<()> {
+        let pb = indicatif::ProgressBar::hidden();
+        // The first two attempts fail with a server error
+        let attempts = Cell::new(0);
+        let result = retry(3, &pb, || {
+            attempts.set(attempts.get() + 1);
+            let attempt = attempts.get();
+            let error = api_error("<html><h1>503 Service Unavailable</h1></html>");
+            async move {
+                if attempt <= 2 {
+                    return Err(error);
+                }
+                Ok(attempt)
+            }
+        })
+        .await?;
+        assert_eq!(result, 3);
+
+        // Gives up after `max_retries` retries
+        attempts.set(0);
+        let result = retry(2, &pb, || {
+            attempts.set(attempts.get() + 1);
+            let error = api_error("<html><h1>502 Bad Gateway</h1></html>");
+            async { Err::<(), _>(error) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 3);
+
+        // Other errors are returned right away
+        attempts.set(0);
+        let result = retry(3, &pb, || {
+            attempts.set(attempts.get() + 1);
+            let error = api_error(r#"{"status": "failure", "reason": "Invalid API key"}"#);
+            async { Err::<(), _>(error) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn ignore_tags() {
+        let ignore_tags = vec!["private".to_owned(), "tmp/*".to_owned()];
+        // exact match
+        assert!(is_ignored_tag("private", &ignore_tags));
+        assert!(!is_ignored_tag("private/notes", &ignore_tags));
+        // prefix match
+        assert!(is_ignored_tag("tmp/draft", &ignore_tags));
+        assert!(is_ignored_tag("tmp/", &ignore_tags));
+        // neither
+        assert!(!is_ignored_tag("tmp", &ignore_tags));
+        assert!(!is_ignored_tag("rust", &ignore_tags));
+    }
+
+    #[test]
+    fn uri_filenames() {
+        let dots = uri_to_filename("https://example.com/a.b");
+        let slashes = uri_to_filename("https://example.com/a/b");
+        assert!(dots.starts_with("example_com_a_b_"));
+        assert!(slashes.starts_with("example_com_a_b_"));
+        assert_ne!(dots, slashes);
+        // The same URI always gets the same filename
+        assert_eq!(dots, uri_to_filename("https://example.com/a.b"));
+    }
+
+    #[test]
+    fn html_titles() {
+        assert_eq!(
+            html_title("<html><head><TITLE>\n  Rust &amp; Cargo\n  </TITLE></head></html>"),
+            Some("Rust & Cargo".to_owned())
+        );
+        assert_eq!(
+            html_title("<title lang=\"en\">Docs</title>"),
+            Some("Docs".to_owned())
+        );
+        assert_eq!(html_title("<title> </title>"), None);
+        assert_eq!(html_title("<p>No title</p>"), None);
+    }
+
+    #[test]
+    fn registrable_domains() {
+        assert_eq!(
+            registrable_domain("https://en.wikipedia.org/wiki/Rust"),
+            "wikipedia.org"
+        );
+        assert_eq!(
+            registrable_domain("https://www.bbc.co.uk/news"),
+            "bbc.co.uk"
+        );
+        assert_eq!(registrable_domain("urn:x-pdf:1234"), "urn:x-pdf:1234");
+    }
 }
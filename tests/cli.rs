@@ -12,6 +12,7 @@ use assert_cmd::Command;
 use color_eyre::eyre::WrapErr;
 use eyre::eyre;
 use futures::future::{join_all, try_join_all};
+use predicates::prelude::*;
 use tempfile::{tempdir, TempDir};
 
 fn make_config_file(
@@ -525,6 +526,646 @@ async fn make() -> color_eyre::Result<()> {
         .join("test_tag6")
         .join("test_tag7.md")
         .exists());
+
+    // indent nested tags in the index
+    let config_contents = fs::read_to_string(&test_data.config_file)?.replace(
+        gooseberry::configuration::DEFAULT_INDEX_LINK_TEMPLATE,
+        // TOML drops the newline right after the opening quotes
+        "\n\n{{indent}}- [{{name}}]({{relative_path}})",
+    );
+    fs::write(&test_data.config_file, config_contents)?;
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    cmd.env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("index")
+        .assert()
+        .success();
+    let index_contents =
+        fs::read_to_string(test_data.temp_dir.path().join("kb").join("SUMMARY.md"))?;
+    assert!(index_contents.contains("\n- [test_tag1](test_tag1.md)"));
+    assert!(index_contents.contains("\n  - [test_tag7](test_tag6/test_tag7.md)"));
+
+    // wikilinks instead of the default markdown links
+    let config_contents = fs::read_to_string(&test_data.config_file)?.replace(
+        "\n\n{{indent}}- [{{name}}]({{relative_path}})",
+        gooseberry::configuration::DEFAULT_INDEX_LINK_TEMPLATE,
+    );
+    fs::write(&test_data.config_file, config_contents)?;
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    cmd.env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("index")
+        .assert()
+        .success();
+    let index_contents =
+        fs::read_to_string(test_data.temp_dir.path().join("kb").join("SUMMARY.md"))?;
+    assert!(index_contents.contains("- [test tag5](test%20tag5.md)"));
+    fs::write(
+        &test_data.config_file,
+        format!(
+            "{}\nlink_style = 'wikilink'",
+            fs::read_to_string(&test_data.config_file)?
+        ),
+    )?;
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    cmd.env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("index")
+        .assert()
+        .success();
+    let index_contents =
+        fs::read_to_string(test_data.temp_dir.path().join("kb").join("SUMMARY.md"))?;
+    assert!(index_contents.contains("- [[test tag5]]"));
+    assert!(!index_contents.contains("%20"));
+    test_data.clear().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn make_long_tags() -> color_eyre::Result<()> {
+    let test_data = TestData::populate().await?;
+    let duration = time::Duration::from_millis(500);
+    thread::sleep(duration);
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    cmd.env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("sync")
+        .assert()
+        .success();
+
+    // two tags that only differ after the first 250 characters
+    let prefix = "t".repeat(250);
+    for (text, suffix) in [("a test comment", "a"), ("another test comment", "b")] {
+        thread::sleep(duration);
+        let mut cmd = Command::cargo_bin("gooseberry")?;
+        cmd.env("GOOSEBERRY_CONFIG", &test_data.config_file)
+            .arg("tag")
+            .arg(format!("--text={}", text))
+            .arg(format!("{}{}", prefix, suffix.repeat(50)))
+            .assert()
+            .success();
+    }
+
+    thread::sleep(duration);
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    cmd.env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("make")
+        .arg("-f")
+        .arg("-c")
+        .arg("--no-index")
+        .assert()
+        .success();
+
+    // both pages are written
+    let num_long_pages = fs::read_dir(test_data.temp_dir.path().join("kb"))?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("tttt"))
+        .count();
+    assert_eq!(num_long_pages, 2);
+
+    // shorter file names
+    fs::write(
+        &test_data.config_file,
+        format!(
+            "{}\nmax_filename_length = 15",
+            fs::read_to_string(&test_data.config_file)?
+        ),
+    )?;
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    cmd.env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("make")
+        .arg("-f")
+        .arg("-c")
+        .arg("--no-index")
+        .assert()
+        .success();
+    let file_names = fs::read_dir(test_data.temp_dir.path().join("kb"))?
+        .filter_map(Result::ok)
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    assert!(file_names.iter().all(|name| name.chars().count() <= 15));
+    assert!(file_names.contains(&"test_tag.md".to_owned()));
+    assert_eq!(
+        file_names
+            .iter()
+            .filter(|name| name.starts_with("tttt"))
+            .count(),
+        2
+    );
+    test_data.clear().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn template_helpers() -> color_eyre::Result<()> {
+    // get test_data
+    let test_data = TestData::populate().await;
+    assert!(test_data.is_ok());
+    let test_data = test_data?;
+    let duration = time::Duration::from_millis(500);
+
+    // render annotations with the helpers
+    let config_contents = fs::read_to_string(&test_data.config_file)?.replace(
+        gooseberry::configuration::DEFAULT_ANNOTATION_TEMPLATE,
+        "\n\ntruncated: {{truncate text 12}}\nshort: {{truncate text 100}}\nslug: {{slugify \"Über: Rust//Tips\"}}\nuri slug: {{slugify uri}}\nutc: {{date_format \"%z\" created}}\nlocal: {{date_format \"%z\" created \"Asia/Kolkata\"}}\n",
+    );
+    fs::write(&test_data.config_file, config_contents)?;
+
+    // sync
+    thread::sleep(duration);
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    cmd.env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("sync")
+        .assert()
+        .stdout(predicates::str::contains("Added 2 annotations"));
+
+    // make
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    cmd.env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("make")
+        .arg("-f")
+        .arg("-c")
+        .arg("--no-index")
+        .assert()
+        .success();
+    let page = fs::read_to_string(test_data.temp_dir.path().join("kb").join("test_tag1.md"))?;
+    assert!(page.contains("truncated: this is a…\n"));
+    assert!(page.contains("short: this is a test comment\n"));
+    assert!(page.contains("slug: über-rust-tips\n"));
+    assert!(page.contains("uri slug: https-www-example-com\n"));
+    assert!(page.contains("utc: +0000\n"));
+    assert!(page.contains("local: +0530\n"));
+
     test_data.clear().await?;
     Ok(())
 }
+
+#[tokio::test]
+async fn date_filter() -> color_eyre::Result<()> {
+    // get test_data
+    let test_data = TestData::populate().await;
+    assert!(test_data.is_ok());
+    let test_data = test_data?;
+    let duration = time::Duration::from_millis(500);
+
+    // sync
+    thread::sleep(duration);
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    cmd.env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("sync")
+        .assert()
+        .stdout(predicates::str::contains("Added 2 annotations"));
+
+    // `search` filters with the same function as `uri`
+    // created after yesterday
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    cmd.env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("uri")
+        .arg("--tags=test_tag")
+        .arg("--from=yesterday")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("https://www.example.com"));
+
+    // created before 2000
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    cmd.env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("uri")
+        .arg("--tags=test_tag")
+        .arg("--before=2000-01-01")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("https://www.example.com").not());
+
+    test_data.clear().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn export_json() -> color_eyre::Result<()> {
+    // get test_data
+    let test_data = TestData::populate().await;
+    assert!(test_data.is_ok());
+    let test_data = test_data?;
+    let duration = time::Duration::from_millis(500);
+
+    // sync
+    thread::sleep(duration);
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    cmd.env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("sync")
+        .assert()
+        .stdout(predicates::str::contains("Added 2 annotations"));
+
+    // exported JSON reads back into the same annotations
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    let output = cmd
+        .env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("export")
+        .arg("--tags=test_tag")
+        .arg("--format=json")
+        .output()?;
+    assert!(output.status.success());
+    let exported: Vec<hypothesis::annotations::Annotation> =
+        serde_json::from_slice(&output.stdout)?;
+    assert_eq!(
+        exported.iter().map(|a| &a.id).collect::<HashSet<_>>(),
+        test_data
+            .annotations
+            .iter()
+            .map(|a| &a.id)
+            .collect::<HashSet<_>>()
+    );
+
+    test_data.clear().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn export_csv() -> color_eyre::Result<()> {
+    // get test_data
+    let test_data = TestData::populate().await;
+    assert!(test_data.is_ok());
+    let test_data = test_data?;
+    let duration = time::Duration::from_millis(500);
+
+    // sync
+    thread::sleep(duration);
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    cmd.env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("sync")
+        .assert()
+        .stdout(predicates::str::contains("Added 2 annotations"));
+
+    // header row and one row per annotation
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    let output = cmd
+        .env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("export")
+        .arg("--tags=test_tag")
+        .arg("--format=csv")
+        .output()?;
+    assert!(output.status.success());
+    let mut reader = csv::Reader::from_reader(output.stdout.as_slice());
+    assert_eq!(
+        reader.headers()?,
+        vec!["id", "created", "updated", "uri", "title", "tags", "quote", "text"]
+    );
+    assert_eq!(reader.records().collect::<Result<Vec<_>, _>>()?.len(), 2);
+
+    test_data.clear().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn export_anki() -> color_eyre::Result<()> {
+    // get test_data
+    let test_data = TestData::populate().await;
+    assert!(test_data.is_ok());
+    let mut test_data = test_data?;
+    let duration = time::Duration::from_millis(500);
+
+    // make a highlight with a comment that needs escaping
+    let group_id = dotenv::var("TEST_GROUP_ID")?;
+    let highlight = hypothesis::annotations::InputAnnotation::builder()
+        .uri("https://www.example.com")
+        .text("a <b>bold</b> & two\nline comment")
+        .tags(vec!["test_tag".into(), "anki card".into()])
+        .target(
+            hypothesis::annotations::Target::builder()
+                .source("https://www.example.com")
+                .selector(vec![hypothesis::annotations::Selector::new_quote(
+                    "highlighted text",
+                    "",
+                    "",
+                )])
+                .build()?,
+        )
+        .group(&group_id)
+        .build()?;
+    test_data.annotations.push(
+        test_data
+            .hypothesis_client
+            .create_annotation(&highlight)
+            .await?,
+    );
+
+    // sync
+    thread::sleep(duration);
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    cmd.env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("sync")
+        .assert()
+        .stdout(predicates::str::contains("Added 3 annotations"));
+
+    // the page notes have no quote so they're skipped, the highlight's comment is escaped but the deck name isn't
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    let output = cmd
+        .env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("export")
+        .arg("--tags=test_tag")
+        .arg("--format=anki")
+        .arg("--deck-name=test & deck")
+        .output()?;
+    assert!(output.status.success());
+    let output = String::from_utf8(output.stdout)?;
+    assert_eq!(
+        output.lines().collect::<Vec<_>>(),
+        vec![
+            "#separator:tab",
+            "#html:true",
+            "#deck column:1",
+            "#tags column:4",
+            "test & deck\thighlighted text\ta &lt;b&gt;bold&lt;/b&gt; &amp; two<br>line comment\ttest_tag anki_card"
+        ]
+    );
+
+    test_data.clear().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn move_filter() -> color_eyre::Result<()> {
+    // get test_data
+    let test_data = TestData::populate().await;
+    assert!(test_data.is_ok());
+    let test_data = test_data?;
+    let group_id = dotenv::var("TEST_GROUP_ID")?;
+    let source_group_id = dotenv::var("TEST_SOURCE_GROUP_ID")?;
+    let duration = time::Duration::from_millis(500);
+
+    // make annotations in the source group
+    let annotation_1 = hypothesis::annotations::InputAnnotation::builder()
+        .uri("https://www.example.com")
+        .text("this is a test comment to move")
+        .tags(vec!["test_tag".into(), "test_tag_move".into()])
+        .group(&source_group_id)
+        .build()?;
+    let annotation_2 = hypothesis::annotations::InputAnnotation::builder()
+        .uri("https://www.example.com")
+        .text("this is a test comment to leave")
+        .tags(vec!["test_tag".into()])
+        .group(&source_group_id)
+        .build()?;
+    let a1 = test_data
+        .hypothesis_client
+        .create_annotation(&annotation_1)
+        .await?;
+    let a2 = test_data
+        .hypothesis_client
+        .create_annotation(&annotation_2)
+        .await?;
+
+    // move only the tagged annotation, without the search window
+    thread::sleep(duration);
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    cmd.env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("move")
+        .arg(&source_group_id)
+        .arg("--tags=test_tag_move")
+        .assert()
+        .success();
+
+    // moved to gooseberry's group
+    assert_eq!(
+        test_data
+            .hypothesis_client
+            .fetch_annotation(&a1.id)
+            .await?
+            .group,
+        group_id
+    );
+    // still in the source group
+    assert_eq!(
+        test_data
+            .hypothesis_client
+            .fetch_annotation(&a2.id)
+            .await?
+            .group,
+        source_group_id
+    );
+
+    // delete the annotation left in the source group, the moved one is deleted with the others
+    test_data
+        .hypothesis_client
+        .delete_annotations(&[a2.id])
+        .await?;
+    test_data.clear().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn view_count() -> color_eyre::Result<()> {
+    let test_data = TestData::populate().await?;
+    let duration = time::Duration::from_millis(500);
+    thread::sleep(duration);
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    cmd.env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("sync")
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    cmd.env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("view")
+        .arg("--tags=test_tag")
+        .arg("--count")
+        .assert()
+        .success()
+        .stdout("2\n");
+    // only the annotations that would be viewed are counted
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    cmd.env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("view")
+        .arg("--tags=test_tag")
+        .arg("--count")
+        .arg("--limit=1")
+        .assert()
+        .success()
+        .stdout("1\n");
+    test_data.clear().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn undo_delete() -> color_eyre::Result<()> {
+    // get test_data
+    let test_data = TestData::populate().await;
+    assert!(test_data.is_ok());
+    let test_data = test_data?;
+    let duration = time::Duration::from_millis(1000);
+
+    // sync
+    thread::sleep(duration);
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    cmd.env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("sync")
+        .assert()
+        .stdout(predicates::str::contains("Added 2 annotations"));
+
+    // delete the annotation tagged test_tag2
+    thread::sleep(duration);
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    cmd.env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("delete")
+        .arg("--tags=test_tag2")
+        .arg("-f")
+        .assert()
+        .success();
+    assert!(test_data
+        .hypothesis_client
+        .fetch_annotation(&test_data.annotations[1].id)
+        .await
+        .is_err());
+
+    // undo recreates it, with a new ID
+    thread::sleep(duration);
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    cmd.env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("undo")
+        .assert()
+        .stdout(predicates::str::contains("Recreated 1 annotation(s)"));
+    thread::sleep(duration);
+    let mut query = hypothesis::annotations::SearchQuery::builder()
+        .user(&test_data.hypothesis_client.user.0)
+        .build()?;
+    query.tags = vec!["test_tag2".to_owned()];
+    let recreated = test_data
+        .hypothesis_client
+        .search_annotations(&query)
+        .await?;
+    assert_eq!(recreated.len(), 1);
+    assert_eq!(recreated[0].text, "this is another test comment");
+    assert!(recreated[0].tags.contains(&"test_tag".to_owned()));
+
+    // nothing left to undo
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    cmd.env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("undo")
+        .assert()
+        .stdout(predicates::str::contains("Nothing to undo"));
+
+    // clear, which also deletes the recreated annotation
+    test_data.clear().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn make_frontmatter() -> color_eyre::Result<()> {
+    let test_data = TestData::populate().await?;
+    let duration = time::Duration::from_millis(500);
+    thread::sleep(duration);
+    fs::write(
+        &test_data.config_file,
+        format!(
+            "{}\npage_frontmatter_template = '''\ntitle: {{{{yaml_string name}}}}\n'''",
+            fs::read_to_string(&test_data.config_file)?
+        ),
+    )?;
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    cmd.env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("sync")
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    cmd.env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("make")
+        .arg("-f")
+        .arg("-c")
+        .arg("--no-index")
+        .assert()
+        .success();
+
+    // the page starts with a frontmatter block
+    let page = fs::read_to_string(test_data.temp_dir.path().join("kb").join("test_tag.md"))?;
+    let mut lines = page.lines();
+    assert_eq!(lines.next(), Some("---"));
+    assert_eq!(lines.next(), Some("title: \"test_tag\""));
+    assert_eq!(lines.next(), Some("---"));
+    test_data.clear().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn make_uri_aliases() -> color_eyre::Result<()> {
+    let test_data = TestData::populate().await?;
+    let duration = time::Duration::from_millis(500);
+    thread::sleep(duration);
+    fs::write(
+        &test_data.config_file,
+        format!(
+            "{}\n[uri_aliases]\n\"https://www.example.com/\" = \"Example\"\n",
+            fs::read_to_string(&test_data.config_file)?
+                .replace("hierarchy = ['Tag']", "hierarchy = ['BaseURI']")
+        ),
+    )?;
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    cmd.env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("sync")
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("gooseberry")?;
+    cmd.env("GOOSEBERRY_CONFIG", &test_data.config_file)
+        .arg("make")
+        .arg("-f")
+        .arg("-c")
+        .arg("--no-index")
+        .assert()
+        .success();
+
+    // the aliased base URI gives the page its name
+    let kb_dir = test_data.temp_dir.path().join("kb");
+    assert!(kb_dir.join("Example.md").exists());
+    assert_eq!(fs::read_dir(&kb_dir)?.count(), 1);
+    test_data.clear().await?;
+    Ok(())
+}
+
+#[test]
+fn dynamic_completions() -> color_eyre::Result<()> {
+    let temp_dir = tempdir()?;
+    let config_file = make_config_file(&temp_dir, "username", "key", "group_id")?;
+    // group names come from the config, without asking Hypothesis
+    Command::cargo_bin("gooseberry")?
+        .env("GOOSEBERRY_CONFIG", &config_file)
+        .arg("__complete-groups")
+        .assert()
+        .success()
+        .stdout("test_group\n");
+
+    let bash = Command::cargo_bin("gooseberry")?
+        .arg("complete")
+        .arg("bash")
+        .output()?;
+    let bash = String::from_utf8(bash.stdout)?;
+    assert!(bash.contains(r"local IFS=$'\n'"));
+    // complete a group name after --groups, with this gooseberry first on the PATH
+    let script = temp_dir.path().join("gooseberry.bash");
+    fs::write(&script, &bash)?;
+    let bin_dir = assert_cmd::cargo::cargo_bin("gooseberry")
+        .parent()
+        .ok_or(eyre!("No directory for the gooseberry binary"))?
+        .to_owned();
+    Command::new("bash")
+        .env(
+            "PATH",
+            format!("{}:{}", bin_dir.display(), std::env::var("PATH")?),
+        )
+        .env("GOOSEBERRY_CONFIG", &config_file)
+        .arg("-c")
+        .arg(format!(
+            "source {:?}; COMP_WORDS=(gooseberry view --groups test_); COMP_CWORD=3; _gooseberry_dynamic; printf '%s\\n' \"${{COMPREPLY[@]}}\"",
+            script
+        ))
+        .assert()
+        .success()
+        .stdout("test_group\n");
+    let zsh = Command::cargo_bin("gooseberry")?
+        .arg("complete")
+        .arg("zsh")
+        .output()?;
+    let zsh = String::from_utf8(zsh.stdout)?;
+    // When autoloaded from fpath, only the dynamic completion function is called
+    assert_eq!(
+        zsh.matches(r#"if [ "$funcstack[1]" = "_gooseberry" ]; then"#)
+            .count(),
+        1
+    );
+    assert!(zsh.contains("    _gooseberry_dynamic \"$@\"\nfi"));
+    Ok(())
+}